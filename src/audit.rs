@@ -0,0 +1,290 @@
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::hooks::{TransferRecord, TransferResult};
+use crate::ls_remote::{csv_record, Format};
+use crate::repo;
+
+/// git config key of the audit log, a file path or `off`
+pub const FILE_CONFIG_KEY: &str = "lfs-web3.audit.file";
+
+/// a transfer as recorded in the audit log, one JSON object per line
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    pub operation: String,
+    pub oid: String,
+    pub size: u64,
+    /// the git remote the transfer was for, as git-lfs passed it
+    pub remote: String,
+    pub provider: String,
+    pub address: Option<String>,
+    pub duration_ms: u64,
+    pub result: TransferResult,
+    pub error: Option<String>,
+    /// `user.name <user.email>` from git config
+    pub user: Option<String>,
+}
+
+impl AuditRecord {
+    pub fn new(record: &TransferRecord, remote: &str, user: Option<String>) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            operation: record.operation.to_string(),
+            oid: record.oid.clone(),
+            size: record.size,
+            remote: remote.to_string(),
+            provider: record.provider.to_string(),
+            address: record.address.clone(),
+            duration_ms: record.duration_ms,
+            result: record.result,
+            error: record.error.clone(),
+            user,
+        }
+    }
+}
+
+/// The append-only audit log of the repository.
+///
+/// Defaults to `<lfs dir>/logs/git-lfs-web3-audit.jsonl`, `lfs-web3.audit.file` moves it or turns it `off`.
+#[derive(Clone, Debug, Default)]
+pub struct AuditLog {
+    path: Option<PathBuf>,
+    user: Option<String>,
+}
+
+impl AuditLog {
+    pub fn from_config() -> Self {
+        let repo = match repo::open() {
+            Ok(repo) => repo,
+            Err(_) => return Self::default(),
+        };
+        let config = match repo::config() {
+            Ok(config) => config,
+            Err(_) => return Self::default(),
+        };
+
+        let path = match config.get_path(FILE_CONFIG_KEY) {
+            Ok(path) if path == Path::new("off") => None,
+            Ok(path) => Some(path),
+            Err(_) => Some(repo::lfs_dir(&repo).join("logs").join("git-lfs-web3-audit.jsonl")),
+        };
+
+        let user = match (config.get_string("user.name"), config.get_string("user.email")) {
+            (Ok(name), Ok(email)) => Some(format!("{} <{}>", name, email)),
+            (Ok(name), Err(_)) => Some(name),
+            (Err(_), Ok(email)) => Some(format!("<{}>", email)),
+            (Err(_), Err(_)) => None,
+        };
+
+        Self { path, user }
+    }
+
+    pub fn path(&self) -> Option<&PathBuf> {
+        self.path.as_ref()
+    }
+
+    /// record a finished transfer, failing to do so is only logged
+    pub fn append(&self, record: &TransferRecord, remote: &str) {
+        if let Some(path) = &self.path {
+            let record = AuditRecord::new(record, remote, self.user.clone());
+
+            if let Err(err) = append_to(path, &record) {
+                log::warn!("could not write to the audit log {}: {:#}", path.display(), err);
+            }
+        }
+    }
+}
+
+fn append_to(path: &Path, record: &AuditRecord) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+
+    // a single write, so lines of concurrent transfers don't interleave
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(&line)?;
+
+    Ok(())
+}
+
+/// which audit records to show, every filter that is set has to match
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// oid or oid prefix
+    pub oid: Option<String>,
+    pub operation: Option<String>,
+    /// part of the user name or email
+    pub user: Option<String>,
+    pub remote: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub failed: bool,
+}
+
+impl Filter {
+    fn matches(&self, record: &AuditRecord) -> bool {
+        self.oid.as_ref().map_or(true, |oid| record.oid.starts_with(oid.as_str()))
+            && self.operation.as_ref().map_or(true, |operation| &record.operation == operation)
+            && self.user.as_ref().map_or(true, |user| {
+                record.user.as_ref().map_or(false, |recorded| recorded.contains(user.as_str()))
+            })
+            && self.remote.as_ref().map_or(true, |remote| &record.remote == remote)
+            && self.since.map_or(true, |since| record.timestamp >= since)
+            && (!self.failed || record.result == TransferResult::Failed)
+    }
+}
+
+/// parse `--since`, an RFC 3339 time or a date
+pub fn parse_since(since: &str) -> Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(since) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    let date = NaiveDate::parse_from_str(since, "%Y-%m-%d")
+        .with_context(|| format!("'{}' is neither a date nor an RFC 3339 time", since))?;
+
+    Ok(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).expect("midnight is a valid time")))
+}
+
+/// the records of the audit log that match the filter, oldest first
+pub fn query(log: &AuditLog, filter: &Filter) -> Result<Vec<AuditRecord>> {
+    let path = log
+        .path()
+        .ok_or_else(|| anyhow::anyhow!("the audit log is turned off with {}", FILE_CONFIG_KEY))?;
+
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err).with_context(|| format!("could not open {}", path.display())),
+    };
+
+    let mut records = vec![];
+
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: AuditRecord = serde_json::from_str(&line)
+            .with_context(|| format!("{}:{} is not an audit record", path.display(), number + 1))?;
+
+        if filter.matches(&record) {
+            records.push(record);
+        }
+    }
+
+    Ok(records)
+}
+
+pub fn render(records: &[AuditRecord], format: Format) -> Result<String> {
+    let columns = |record: &AuditRecord| {
+        [
+            record.timestamp.to_rfc3339(),
+            record.operation.clone(),
+            record.oid.clone(),
+            record.size.to_string(),
+            record.remote.clone(),
+            record.provider.clone(),
+            record.user.clone().unwrap_or_default(),
+            record.result.label().to_string(),
+        ]
+    };
+
+    match format {
+        Format::Json => Ok(serde_json::to_string_pretty(records)?),
+        Format::Csv => {
+            let mut csv = String::from("timestamp,operation,oid,size,remote,provider,user,result\n");
+            for record in records {
+                csv.push_str(&csv_record(&columns(record)));
+            }
+            Ok(csv)
+        }
+        Format::Table => {
+            let mut table = format!(
+                "{:<25}  {:<8}  {:<64}  {:>12}  {:<15}  {}\n",
+                "TIME", "OP", "OID", "SIZE", "RESULT", "USER"
+            );
+            for record in records {
+                let [timestamp, operation, oid, size, _remote, _provider, user, result] = columns(record);
+                table.push_str(&format!(
+                    "{:<25}  {:<8}  {:<64}  {:>12}  {:<15}  {}\n",
+                    timestamp, operation, oid, size, result, user
+                ));
+            }
+            Ok(table)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const OID: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+    fn record(operation: &'static str, result: TransferResult) -> TransferRecord {
+        TransferRecord {
+            operation,
+            oid: OID.to_string(),
+            size: 11,
+            provider: "storj",
+            address: Some(OID.to_string()),
+            duration_ms: 42,
+            result,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn appended_records_are_queried_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = AuditLog {
+            path: Some(dir.path().join("logs").join("audit.jsonl")),
+            user: Some("Alice <alice@example.com>".to_string()),
+        };
+
+        log.append(&record("upload", TransferResult::Transferred), "origin");
+        log.append(&record("download", TransferResult::Failed), "origin");
+
+        let all = query(&log, &Filter::default()).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].user.as_deref(), Some("Alice <alice@example.com>"));
+        assert_eq!(all[0].remote, "origin");
+
+        let failed = query(&log, &Filter { failed: true, ..Default::default() }).unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].operation, "download");
+
+        let other_user = Filter { user: Some("bob".to_string()), ..Default::default() };
+        assert!(query(&log, &other_user).unwrap().is_empty());
+
+        let by_prefix = Filter { oid: Some(OID[..8].to_string()), operation: Some("upload".to_string()), ..Default::default() };
+        assert_eq!(query(&log, &by_prefix).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn csv_quotes_user_names() {
+        let record = AuditRecord::new(&record("upload", TransferResult::Transferred), "origin", Some("Doe, Jane".to_string()));
+        let csv = render(&[record], Format::Csv).unwrap();
+
+        assert!(csv.lines().nth(1).unwrap().ends_with(",storj,\"Doe, Jane\",transferred"));
+    }
+
+    #[test]
+    fn since_accepts_dates_and_times() {
+        assert_eq!(parse_since("2024-03-01").unwrap(), Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap());
+        assert_eq!(parse_since("2024-03-01T12:00:00+02:00").unwrap(), Utc.with_ymd_and_hms(2024, 3, 1, 10, 0, 0).unwrap());
+        assert!(parse_since("last tuesday").is_err());
+    }
+}
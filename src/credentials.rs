@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::env;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use anyhow::{Context, Result};
+
+use crate::logging;
+
+/// which settings of a provider are credentials and which URL they belong to
+#[derive(Clone, Copy, Debug)]
+pub struct CredentialSpec {
+    pub provider: &'static str,
+    /// every setting the provider reads
+    pub vars: &'static [&'static str],
+    /// setting holding the URL the credentials are for
+    pub url_var: &'static str,
+    pub default_url: &'static str,
+    /// setting stored as the credential's username, if the provider has one
+    pub username_var: Option<&'static str>,
+    /// setting stored as the credential's password
+    pub password_var: &'static str,
+}
+
+impl CredentialSpec {
+    /// the settings that are needed to authenticate
+    pub fn required_vars(&self) -> Vec<&'static str> {
+        self.username_var.into_iter().chain(Some(self.password_var)).collect()
+    }
+}
+
+/// a username and password as exchanged with `git credential`
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Credential {
+    username: Option<String>,
+    password: Option<String>,
+}
+
+/// user-level settings file: `$XDG_CONFIG_HOME/git-lfs-web3/<provider>.env`
+pub fn user_env_file(provider: &str) -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("git-lfs-web3").join(format!("{}.env", provider)))
+}
+
+/// legacy settings file in the current directory: `.<provider>.env`
+pub fn legacy_env_file(provider: &str) -> PathBuf {
+    PathBuf::from(format!(".{}.env", provider))
+}
+
+fn read_env_file(path: &PathBuf) -> Result<HashMap<String, String>> {
+    if !path.is_file() {
+        return Ok(HashMap::new());
+    }
+
+    env_file_reader::read_file(path)
+        .with_context(|| format!("could not read {}", path.display()))
+}
+
+/// Resolve the settings of a provider.
+///
+/// Every setting is looked up in the process environment, then in git's credential helpers
+/// (credentials only, keyed by the portal or endpoint URL), then in the user-level config
+/// file and finally in the legacy env file in the current directory. Credentials that were
+/// found outside of the credential helpers are handed to them, so they only have to be
+/// configured once.
+pub fn resolve(spec: &CredentialSpec) -> Result<HashMap<String, String>> {
+    let mut files = read_env_file(&legacy_env_file(spec.provider))?;
+
+    if let Some(user_file) = user_env_file(spec.provider) {
+        files.extend(read_env_file(&user_file)?);
+    }
+
+    let mut vars = HashMap::new();
+
+    for var in spec.vars {
+        if let Ok(value) = env::var(var) {
+            vars.insert(var.to_string(), value);
+        }
+    }
+
+    let url = vars
+        .get(spec.url_var)
+        .or_else(|| files.get(spec.url_var))
+        .cloned()
+        .unwrap_or_else(|| spec.default_url.to_string());
+
+    let credential_vars = spec.required_vars();
+
+    if credential_vars.iter().any(|var| !vars.contains_key(*var)) {
+        let from_helper = fill(&url).unwrap_or_else(|err| {
+            log::debug!("git credential fill failed for {}: {:#}", &url, err);
+            Credential::default()
+        });
+
+        if from_helper.password.is_some() {
+            log::debug!("using credentials from git credential helper for {}", &url);
+
+            if let (Some(var), Some(username)) = (spec.username_var, from_helper.username) {
+                vars.entry(var.to_string()).or_insert(username);
+            }
+            if let Some(password) = from_helper.password {
+                vars.entry(spec.password_var.to_string()).or_insert(password);
+            }
+        } else {
+            let from_files = Credential {
+                username: spec.username_var.and_then(|var| files.get(var).cloned()),
+                password: files.get(spec.password_var).cloned(),
+            };
+
+            if from_files.password.is_some() {
+                if let Err(err) = approve(&url, &from_files) {
+                    log::debug!("git credential approve failed for {}: {:#}", &url, err);
+                }
+            }
+        }
+    }
+
+    for (var, value) in files {
+        if spec.vars.contains(&var.as_str()) {
+            vars.entry(var).or_insert(value);
+        }
+    }
+
+    for var in credential_vars {
+        if let Some(secret) = vars.get(var) {
+            logging::register_secret(secret);
+        }
+    }
+
+    log::debug!("{} settings: {:?}", spec.provider, vars.keys().collect::<Vec<_>>());
+
+    Ok(vars)
+}
+
+/// run `git credential <action>` non-interactively, writing `input` to it
+fn git_credential(action: &str, input: &str) -> Result<String> {
+    let mut child = Command::new("git")
+        .args(&["credential", action])
+        // we run inside git-lfs, there is nobody to answer a prompt
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GCM_INTERACTIVE", "never")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    child
+        .stdin
+        .take()
+        .context("no stdin for git credential")?
+        .write_all(input.as_bytes())?;
+
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("git credential {} exited with {}", action, output.status));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn fill(url: &str) -> Result<Credential> {
+    Ok(parse_credential(&git_credential("fill", &format!("url={}\n\n", url))?))
+}
+
+fn approve(url: &str, credential: &Credential) -> Result<()> {
+    let mut input = format!("url={}\n", url);
+
+    if let Some(username) = &credential.username {
+        input.push_str(&format!("username={}\n", username));
+    }
+    if let Some(password) = &credential.password {
+        input.push_str(&format!("password={}\n", password));
+    }
+    input.push('\n');
+
+    git_credential("approve", &input)?;
+    Ok(())
+}
+
+fn parse_credential(output: &str) -> Credential {
+    let mut credential = Credential::default();
+
+    for line in output.lines() {
+        if let Some(username) = line.strip_prefix("username=") {
+            credential.username = Some(username.to_string());
+        } else if let Some(password) = line.strip_prefix("password=") {
+            credential.password = Some(password.to_string());
+        }
+    }
+
+    credential
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parse_credential_reads_username_and_password() {
+        assert_eq!(
+            parse_credential("protocol=https\nhost=gateway.storjshare.io\nusername=access\npassword=secret\n"),
+            Credential {
+                username: Some("access".to_string()),
+                password: Some("secret".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn required_vars_lists_username_before_password() {
+        let spec = CredentialSpec {
+            provider: "test",
+            vars: &["URL", "USER", "PASS"],
+            url_var: "URL",
+            default_url: "https://example.com",
+            username_var: Some("USER"),
+            password_var: "PASS",
+        };
+
+        assert_eq!(spec.required_vars(), vec!["USER", "PASS"]);
+    }
+}
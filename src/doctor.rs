@@ -0,0 +1,272 @@
+use std::fmt::Display;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+use git_lfs_spec::Object;
+use git_lfs_spec::transfer::custom::{Download, Upload};
+
+use crate::credentials;
+use crate::oid::oid_of_bytes;
+use crate::provider::StorageProvider;
+use crate::providers;
+use crate::repo;
+
+/// collects the outcome of every check and prints it as it goes
+#[derive(Default)]
+struct Report {
+    failures: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn ok(&mut self, check: &str, detail: impl Display) {
+        println!("[ ok ] {}: {}", check, detail);
+    }
+
+    fn warn(&mut self, check: &str, detail: impl Display, hint: &str) {
+        self.warnings += 1;
+        println!("[warn] {}: {}", check, detail);
+        println!("       hint: {}", hint);
+    }
+
+    fn fail(&mut self, check: &str, detail: impl Display, hint: &str) {
+        self.failures += 1;
+        println!("[FAIL] {}: {}", check, detail);
+        println!("       hint: {}", hint);
+    }
+}
+
+/// Validate the git-lfs installation, the custom transfer registration and
+/// every provider that has been configured.
+///
+/// Returns whether all checks passed.
+pub async fn doctor() -> Result<bool> {
+    let mut report = Report::default();
+
+    check_git_lfs(&mut report);
+    check_transfer_registration(&mut report);
+
+    let configured = providers::configured_name();
+
+    if !providers::PROVIDERS.contains(&configured.as_str()) {
+        report.fail(
+            "provider",
+            format!("unknown provider '{}'", &configured),
+            &format!("set {} to one of: {}", providers::PROVIDER_CONFIG_KEY, providers::PROVIDERS.join(", "))
+        );
+    }
+
+    for name in providers::PROVIDERS {
+        check_provider(&mut report, name, *name == configured).await;
+    }
+
+    println!();
+    println!("{} failure(s), {} warning(s)", report.failures, report.warnings);
+
+    Ok(report.failures == 0)
+}
+
+fn check_git_lfs(report: &mut Report) {
+    match Command::new("git").args(&["lfs", "version"]).output() {
+        Ok(output) if output.status.success() => {
+            report.ok("git-lfs", String::from_utf8_lossy(&output.stdout).trim())
+        }
+        Ok(output) => report.fail(
+            "git-lfs",
+            String::from_utf8_lossy(&output.stderr).trim(),
+            "install git-lfs from https://git-lfs.github.com/ and run `git lfs install`"
+        ),
+        Err(err) => report.fail(
+            "git-lfs",
+            format!("could not run git: {}", err),
+            "make sure git is installed and on your PATH"
+        ),
+    }
+}
+
+fn check_transfer_registration(report: &mut Report) {
+    let config = match repo::config() {
+        Ok(config) => config,
+        Err(err) => {
+            return report.fail(
+                "repository",
+                format!("{:#}", err),
+                "run git-lfs-web3 doctor from inside the repository you want to use it in"
+            )
+        }
+    };
+
+    if config.get_string("filter.lfs.process").is_err() {
+        report.warn(
+            "lfs filters",
+            "the git-lfs filters are not installed",
+            "run `git lfs install`"
+        );
+    }
+
+    let agent = match config.get_string("lfs.standalonetransferagent") {
+        Ok(agent) => agent,
+        Err(_) => {
+            return report.fail(
+                "transfer agent",
+                "lfs.standalonetransferagent is not set",
+                "run `make config` from the git-lfs-web3 checkout, inside this repository"
+            )
+        }
+    };
+
+    let path = match config.get_string(&format!("lfs.customtransfer.{}.path", &agent)) {
+        Ok(path) => path,
+        Err(_) => {
+            return report.fail(
+                "transfer agent",
+                format!("lfs.customtransfer.{}.path is not set", &agent),
+                "run `make config` from the git-lfs-web3 checkout, inside this repository"
+            )
+        }
+    };
+
+    if !Path::new(&path).is_file() {
+        return report.fail(
+            "transfer agent",
+            format!("{} does not exist", &path),
+            "build the binary with `make` or point lfs.customtransfer.<agent>.path at it"
+        );
+    }
+
+    let args = config
+        .get_string(&format!("lfs.customtransfer.{}.args", &agent))
+        .unwrap_or_default();
+
+    if !args.split_whitespace().any(|arg| arg == "transfer") {
+        return report.fail(
+            "transfer agent",
+            format!("lfs.customtransfer.{}.args is '{}'", &agent, &args),
+            &format!("run `git config lfs.customtransfer.{}.args transfer`", &agent)
+        );
+    }
+
+    report.ok("transfer agent", format!("'{}' registered as {}", &agent, &path));
+}
+
+async fn check_provider(report: &mut Report, name: &str, configured: bool) {
+    let check = format!("provider {}", name);
+
+    let spec = match providers::credential_spec(name) {
+        Some(spec) => spec,
+        None => return,
+    };
+
+    let settings_hint = format!(
+        "set the credentials as env variables, in a git credential helper for the portal or endpoint URL, in {} or in {}",
+        credentials::user_env_file(name)
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| "$XDG_CONFIG_HOME/git-lfs-web3/".to_string()),
+        credentials::legacy_env_file(name).display()
+    );
+
+    let env_variables = match credentials::resolve(spec) {
+        Ok(env_variables) => env_variables,
+        Err(err) => return report.fail(&check, format!("{:#}", err), &settings_hint),
+    };
+
+    let missing: Vec<&str> = spec
+        .required_vars()
+        .into_iter()
+        .filter(|var| !env_variables.contains_key(*var))
+        .collect();
+
+    if !missing.is_empty() {
+        if !configured {
+            return report.ok(&check, "not configured");
+        }
+
+        return report.fail(&check, format!("missing {}", missing.join(", ")), &settings_hint);
+    }
+
+    let provider = match providers::from_name(name) {
+        Ok(provider) => provider,
+        Err(err) => {
+            return report.fail(
+                &check,
+                format!("could not be configured: {:#}", err),
+                &settings_hint
+            )
+        }
+    };
+
+    match round_trip(&*provider).await {
+        Ok(()) => report.ok(&check, "uploaded, found and downloaded a test object"),
+        Err(err) => report.fail(
+            &check,
+            format!("round-trip failed: {:#}", err),
+            "check the portal or endpoint URL and the credentials"
+        ),
+    }
+}
+
+/// Upload a tiny object, check that it exists and download it again.
+///
+/// The object is deleted again afterwards where the provider can delete, which for Skynet also
+/// drops the mapping the upload wrote to git config.
+async fn round_trip(provider: &dyn StorageProvider) -> Result<()> {
+    let contents = format!(
+        "git-lfs-web3 doctor {}",
+        SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos()
+    );
+
+    let object = Object {
+        oid: oid_of_bytes(contents.as_bytes()),
+        size: contents.len() as u64,
+    };
+
+    let mut file = tempfile::NamedTempFile::new()?;
+    file.write_all(contents.as_bytes())?;
+
+    let upload = Upload {
+        object: object.clone(),
+        path: file.path().to_path_buf(),
+    };
+
+    provider.upload(&upload).await.context("upload failed")?;
+
+    let checked = check_uploaded(provider, &upload, contents.as_bytes()).await;
+
+    if provider.capabilities().supports_delete {
+        let deleted = provider
+            .delete(&object.oid)
+            .await
+            .with_context(|| format!("could not delete the test object {}", &object.oid));
+
+        // the outcome of the check matters more than the cleanup
+        checked?;
+        deleted?;
+    } else {
+        checked?;
+    }
+
+    Ok(())
+}
+
+/// check that an uploaded object exists and downloads with the same contents
+async fn check_uploaded(provider: &dyn StorageProvider, upload: &Upload, contents: &[u8]) -> Result<()> {
+    if !provider.is_uploaded(upload).await.context("existence check failed")? {
+        return Err(anyhow::anyhow!("object not found after uploading it"));
+    }
+
+    let path = provider
+        .download(&Download { object: upload.object.clone() })
+        .await
+        .context("download failed")?;
+
+    let downloaded = tokio::fs::read(&path).await?;
+    let _ = tokio::fs::remove_file(&path).await;
+
+    if downloaded != contents {
+        return Err(anyhow::anyhow!("downloaded contents differ from the uploaded contents"));
+    }
+
+    Ok(())
+}
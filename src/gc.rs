@@ -0,0 +1,124 @@
+use std::collections::HashSet;
+use anyhow::Result;
+use chrono::{Duration, Utc};
+
+use crate::pointer;
+use crate::provider::{RemoteObject, StorageProvider};
+use crate::repo;
+
+/// git config key listing the refs whose history keeps objects alive
+pub const GC_REF_CONFIG_KEY: &str = "lfs-web3.gc.ref";
+
+/// Delete the objects held by the provider that no commit reachable from `refs` points to anymore.
+///
+/// Without `prune` this only reports what would be deleted. Objects uploaded within the grace
+/// period, or whose upload time is unknown, are always kept.
+pub async fn gc(client: &dyn StorageProvider, refs: Vec<String>, grace_days: i64, prune: bool) -> Result<bool> {
+    let capabilities = client.capabilities();
+
+    if !capabilities.supports_list {
+        return Err(anyhow::anyhow!("{} can't list the objects it holds, so there is nothing to collect", client.name()));
+    }
+
+    if prune && !capabilities.scoped_list {
+        return Err(anyhow::anyhow!(
+            "{} lists objects of other repositories too, refusing to prune them",
+            client.name()
+        ));
+    }
+
+    let prune = if prune && !capabilities.supports_delete {
+        println!("{} can't delete objects, only reporting them", client.name());
+        false
+    } else {
+        prune
+    };
+
+    let repo = repo::open()?;
+
+    let refs = if refs.is_empty() { configured_refs(&repo)? } else { refs };
+
+    if refs.is_empty() {
+        println!("keeping every object referenced from any ref");
+    } else {
+        println!("keeping every object referenced from {}", refs.join(", "));
+    }
+
+    let referenced: HashSet<String> = pointer::pointers_in_history(&repo, &refs)?
+        .into_keys()
+        .collect();
+
+    let unreferenced: Vec<RemoteObject> = client
+        .list()
+        .await?
+        .into_iter()
+        .filter(|object| !referenced.contains(&object.oid))
+        .collect();
+
+    let cutoff = Utc::now() - Duration::days(grace_days);
+
+    let (expired, recent): (Vec<RemoteObject>, Vec<RemoteObject>) = unreferenced
+        .into_iter()
+        .partition(|object| match object.uploaded_at {
+            Some(uploaded_at) => uploaded_at < cutoff,
+            // such as Skynet mappings from before upload times were recorded
+            None => false,
+        });
+
+    for object in &recent {
+        match object.uploaded_at {
+            Some(_) => println!("keep   {} (within the {} day grace period)", object.oid, grace_days),
+            None => println!("keep   {} (upload time unknown)", object.oid),
+        }
+    }
+
+    let mut failed = 0;
+
+    for object in &expired {
+        if !prune {
+            println!("delete {} {}", object.oid, object.address.as_deref().unwrap_or_default());
+            continue;
+        }
+
+        match client.delete(&object.oid).await {
+            Ok(()) => println!("deleted {} {}", object.oid, object.address.as_deref().unwrap_or_default()),
+            Err(err) => {
+                failed += 1;
+                println!("failed {}: {:#}", object.oid, err);
+            }
+        }
+    }
+
+    println!(
+        "{} unreferenced objects on {}: {} past the grace period, {} kept",
+        expired.len() + recent.len(),
+        client.name(),
+        expired.len(),
+        recent.len()
+    );
+
+    if !prune && !expired.is_empty() {
+        println!("this was a dry run, run again with --prune to delete them");
+    }
+
+    if prune {
+        client.finish().await?;
+    }
+
+    Ok(failed == 0)
+}
+
+fn configured_refs(repo: &git2::Repository) -> Result<Vec<String>> {
+    let config = repo.config()?;
+    let mut refs = vec![];
+
+    for entry in &config.multivar(GC_REF_CONFIG_KEY, None)? {
+        match entry {
+            Ok(entry) => refs.extend(entry.value().map(str::to_string)),
+            Err(err) if err.code() == git2::ErrorCode::NotFound => break,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(refs)
+}
@@ -0,0 +1,201 @@
+use std::process::Stdio;
+use anyhow::{Context, Result};
+use isahc::{AsyncReadResponseExt, RequestExt};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::http::HttpSettings;
+use crate::repo;
+
+/// git config key of a command that receives every hook event as JSON on stdin, run with `sh -c`
+pub const COMMAND_CONFIG_KEY: &str = "lfs-web3.hook.command";
+
+/// git config key of a URL every hook event is POSTed to as JSON
+pub const URL_CONFIG_KEY: &str = "lfs-web3.hook.url";
+
+/// how a transfer ended
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferResult {
+    Transferred,
+    /// the provider had the object already, so it wasn't uploaded
+    AlreadyPresent,
+    Failed,
+}
+
+impl TransferResult {
+    pub fn label(&self) -> &'static str {
+        match self {
+            TransferResult::Transferred => "transferred",
+            TransferResult::AlreadyPresent => "already_present",
+            TransferResult::Failed => "failed",
+        }
+    }
+}
+
+/// a single upload or download, as passed to hooks
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TransferRecord {
+    pub operation: &'static str,
+    pub oid: String,
+    pub size: u64,
+    pub provider: &'static str,
+    /// skylink or key the object is stored under
+    pub address: Option<String>,
+    pub duration_ms: u64,
+    pub result: TransferResult,
+    pub error: Option<String>,
+}
+
+/// what hooks are called with
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum HookEvent {
+    /// an object was transferred, or failed to
+    Complete(TransferRecord),
+    /// git-lfs is done with the batch of transfers
+    Batch {
+        operation: &'static str,
+        provider: &'static str,
+        objects: Vec<TransferRecord>,
+        duration_ms: u64,
+    },
+}
+
+/// The hooks configured for the repository.
+///
+/// Hooks are told about transfers, they can't fail them: errors are only logged.
+#[derive(Clone, Debug, Default)]
+pub struct Hooks {
+    command: Option<String>,
+    url: Option<String>,
+    http: HttpSettings,
+}
+
+impl Hooks {
+    pub fn from_config() -> Self {
+        let config = match repo::config() {
+            Ok(config) => config,
+            Err(_) => return Self::default(),
+        };
+        let setting = |key: &str| config.get_string(key).ok().filter(|value| !value.is_empty());
+
+        Self {
+            command: setting(COMMAND_CONFIG_KEY),
+            url: setting(URL_CONFIG_KEY),
+            http: HttpSettings::from_config(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.command.is_none() && self.url.is_none()
+    }
+
+    pub async fn run(&self, event: &HookEvent) {
+        if self.is_empty() {
+            return;
+        }
+
+        let payload = match serde_json::to_vec(event) {
+            Ok(payload) => payload,
+            Err(err) => {
+                log::warn!("could not serialize hook event: {}", err);
+                return;
+            }
+        };
+
+        if let Some(command) = &self.command {
+            if let Err(err) = run_command(command, &payload).await {
+                log::warn!("hook command failed: {:#}", err);
+            }
+        }
+
+        if let Some(url) = &self.url {
+            if let Err(err) = self.post(url, payload).await {
+                log::warn!("hook request to {} failed: {:#}", url, err);
+            }
+        }
+    }
+
+    async fn post(&self, url: &str, payload: Vec<u8>) -> Result<()> {
+        let mut response = self
+            .http
+            .configure(isahc::Request::post(url))
+            .header("Content-Type", "application/json")
+            .body(payload)?
+            .send_async()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("{} {}", response.status(), response.text().await.unwrap_or_default()));
+        }
+
+        Ok(())
+    }
+}
+
+async fn run_command(command: &str, payload: &[u8]) -> Result<()> {
+    let mut child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        // stdout is where git-lfs reads our responses from
+        .stdout(Stdio::null())
+        .spawn()
+        .with_context(|| format!("could not run {}", command))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(payload).await?;
+    }
+
+    let status = child.wait().await?;
+
+    if !status.success() {
+        return Err(anyhow::anyhow!("{} exited with {}", command, status));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn record() -> TransferRecord {
+        TransferRecord {
+            operation: "upload",
+            oid: "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9".to_string(),
+            size: 11,
+            provider: "storj",
+            address: Some("b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9".to_string()),
+            duration_ms: 42,
+            result: TransferResult::Transferred,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn events_are_tagged() {
+        let json = serde_json::to_value(&HookEvent::Complete(record())).unwrap();
+
+        assert_eq!(json["event"], "complete");
+        assert_eq!(json["result"], "transferred");
+        assert_eq!(json["size"], 11);
+    }
+
+    #[tokio::test]
+    async fn command_receives_the_event_on_stdin() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("event.json");
+        let hooks = Hooks {
+            command: Some(format!("cat > '{}'", output.display())),
+            ..Default::default()
+        };
+
+        hooks.run(&HookEvent::Complete(record())).await;
+
+        let received: serde_json::Value = serde_json::from_slice(&std::fs::read(&output).unwrap()).unwrap();
+        assert_eq!(received["oid"], record().oid);
+    }
+}
@@ -0,0 +1,205 @@
+use std::sync::Mutex;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::oid::oid_of_bytes;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Owner {
+    pub name: String,
+}
+
+/// a lock on a path, as described by the Git LFS locking API
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Lock {
+    pub id: String,
+    pub path: String,
+    pub locked_at: DateTime<Utc>,
+    pub owner: Owner,
+}
+
+impl Lock {
+    pub fn new(path: &str, owner: &str) -> Self {
+        Self {
+            id: lock_id(path),
+            path: path.to_string(),
+            locked_at: Utc::now(),
+            owner: Owner {
+                name: owner.to_string(),
+            },
+        }
+    }
+}
+
+/// a path can only be locked once, so its hash identifies the lock
+pub fn lock_id(path: &str) -> String {
+    oid_of_bytes(path.as_bytes())
+}
+
+/// where a provider keeps lock records
+#[async_trait]
+pub trait LockStore: Sync {
+    /// Store the lock unless its path is locked already.
+    ///
+    /// Returns the existing lock when there is one, `None` when the lock was created.
+    async fn create_lock(&self, lock: &Lock) -> Result<Option<Lock>>;
+
+    /// every lock that is held
+    async fn locks(&self) -> Result<Vec<Lock>>;
+
+    /// release a lock, succeeding if it was already gone
+    async fn remove_lock(&self, id: &str) -> Result<()>;
+}
+
+/// an object store whose writes can be made conditional, the base of atomic lock creation
+#[async_trait]
+pub trait ConditionalStore: Sync {
+    /// Write `body` under `key` only if nothing is stored there yet (`If-None-Match: *`).
+    ///
+    /// Returns whether it was written.
+    async fn put_if_absent(&self, key: &str, body: &[u8]) -> Result<bool>;
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+}
+
+/// Lock records created with conditional writes.
+///
+/// Reading a lock back after an unconditional write can't tell two writers apart, both may see
+/// their own lock before the other one's write is visible. So locks are only created on stores
+/// that were seen refusing a conditional write, by writing a probe object twice.
+pub struct ConditionalLocks {
+    probe: String,
+    enforced: Mutex<Option<bool>>,
+}
+
+impl ConditionalLocks {
+    /// `probe` is the key of the object written to check the store, outside of the lock records
+    pub fn new(probe: String) -> Self {
+        Self {
+            probe,
+            enforced: Mutex::new(None),
+        }
+    }
+
+    /// whether the store refuses conditional writes to existing keys, only checked once
+    async fn enforced(&self, store: &dyn ConditionalStore) -> Result<bool> {
+        if let Some(enforced) = *self.enforced.lock().unwrap() {
+            return Ok(enforced);
+        }
+
+        // a probe left by an earlier check has to be refused right away
+        let enforced = !store.put_if_absent(&self.probe, b"{}").await?
+            || !store.put_if_absent(&self.probe, b"{}").await?;
+
+        if !enforced {
+            log::warn!("the store ignores conditional writes, locking is not available");
+        }
+
+        *self.enforced.lock().unwrap() = Some(enforced);
+
+        Ok(enforced)
+    }
+
+    pub async fn get(&self, store: &dyn ConditionalStore, key: &str) -> Result<Option<Lock>> {
+        match store.get(key).await? {
+            Some(body) => Ok(Some(serde_json::from_slice(&body)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// store the lock under `key` unless it is there already, see `LockStore::create_lock`
+    pub async fn create(&self, store: &dyn ConditionalStore, key: &str, lock: &Lock) -> Result<Option<Lock>> {
+        if !self.enforced(store).await? {
+            return Err(anyhow::anyhow!(
+                "the store doesn't enforce conditional writes, so locks can't be created atomically"
+            ));
+        }
+
+        if let Some(existing) = self.get(store, key).await? {
+            return Ok(Some(existing));
+        }
+
+        if store.put_if_absent(key, &serde_json::to_vec(lock)?).await? {
+            return Ok(None);
+        }
+
+        // somebody else created the lock since we looked
+        match self.get(store, key).await? {
+            Some(existing) => Ok(Some(existing)),
+            None => Err(anyhow::anyhow!("lock on {} was released while creating it, try again", &lock.path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use pretty_assertions::assert_eq;
+
+    /// an in-memory store, optionally ignoring the condition like some S3 gateways do
+    struct MemoryStore {
+        honours_condition: bool,
+        objects: Mutex<HashMap<String, Vec<u8>>>,
+    }
+
+    impl MemoryStore {
+        fn new(honours_condition: bool) -> Self {
+            Self {
+                honours_condition,
+                objects: Mutex::new(HashMap::new()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ConditionalStore for MemoryStore {
+        async fn put_if_absent(&self, key: &str, body: &[u8]) -> Result<bool> {
+            let mut objects = self.objects.lock().unwrap();
+
+            if self.honours_condition && objects.contains_key(key) {
+                return Ok(false);
+            }
+
+            objects.insert(key.to_string(), body.to_vec());
+            Ok(true)
+        }
+
+        async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            Ok(self.objects.lock().unwrap().get(key).cloned())
+        }
+    }
+
+    /// two clients locking the same path, returning how many of them got the lock
+    async fn contend(store: &MemoryStore) -> usize {
+        let alice = Lock::new("assets/file.bin", "alice");
+        let bob = Lock::new("assets/file.bin", "bob");
+        let key = format!("locks/{}", &alice.id);
+
+        // separate instances, like two clients that each checked the store on their own
+        let (first, second) = futures::join!(
+            ConditionalLocks::new("lock-probe".to_string()).create(store, &key, &alice),
+            ConditionalLocks::new("lock-probe".to_string()).create(store, &key, &bob),
+        );
+
+        [first, second]
+            .iter()
+            .filter(|created| matches!(created, Ok(None)))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn only_one_client_gets_a_lock() {
+        assert_eq!(contend(&MemoryStore::new(true)).await, 1);
+    }
+
+    #[tokio::test]
+    async fn stores_ignoring_the_condition_are_refused() {
+        let store = MemoryStore::new(false);
+
+        assert_eq!(contend(&store).await, 0);
+        assert!(!store.objects.lock().unwrap().keys().any(|key| key.starts_with("locks/")));
+    }
+}
@@ -0,0 +1,183 @@
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::RwLock;
+use anyhow::Result;
+use log::{LevelFilter, Record};
+use log4rs::append::console::{ConsoleAppender, Target};
+use log4rs::append::file::FileAppender;
+use log4rs::append::Append;
+use log4rs::config::{Appender, Config, Root};
+use log4rs::encode::json::JsonEncoder;
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::writer::simple::SimpleWriter;
+use log4rs::encode::{self, Encode};
+
+use crate::repo;
+
+/// where to log to: `stderr`, `off` or a file path.
+/// Set with `GIT_LFS_WEB3_LOG` or `lfs-web3.log.file`
+const DESTINATION_ENV_VAR: &str = "GIT_LFS_WEB3_LOG";
+const DESTINATION_CONFIG_KEY: &str = "lfs-web3.log.file";
+
+/// `error`, `warn`, `info`, `debug` or `trace`.
+/// Set with `GIT_LFS_WEB3_LOG_LEVEL` or `lfs-web3.log.level`
+const LEVEL_ENV_VAR: &str = "GIT_LFS_WEB3_LOG_LEVEL";
+const LEVEL_CONFIG_KEY: &str = "lfs-web3.log.level";
+
+/// `text` or `json`.
+/// Set with `GIT_LFS_WEB3_LOG_FORMAT` or `lfs-web3.log.format`
+const FORMAT_ENV_VAR: &str = "GIT_LFS_WEB3_LOG_FORMAT";
+const FORMAT_CONFIG_KEY: &str = "lfs-web3.log.format";
+
+/// env variables whose values never end up in the log
+const SECRET_ENV_VARS: &[&str] = &["SKYNET_API_KEY", "STORJ_S3_ACCESS_KEY", "STORJ_S3_SECRET", "SIA_RENTERD_PASSWORD"];
+
+const REDACTED: &str = "[REDACTED]";
+
+/// values shorter than this are too likely to match unrelated text
+const MIN_SECRET_LEN: usize = 4;
+
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Make sure a credential is scrubbed from every log line written from now on.
+pub fn register_secret(secret: &str) {
+    if secret.len() < MIN_SECRET_LEN {
+        return;
+    }
+
+    if let Ok(mut secrets) = SECRETS.write() {
+        if !secrets.iter().any(|known| known == secret) {
+            secrets.push(secret.to_string());
+        }
+    }
+}
+
+/// replace every registered secret in a log line
+fn redact(line: &str) -> String {
+    let mut line = line.to_string();
+
+    if let Ok(secrets) = SECRETS.read() {
+        for secret in secrets.iter() {
+            line = line.replace(secret.as_str(), REDACTED);
+        }
+    }
+
+    line
+}
+
+/// encodes with the inner encoder and scrubs the registered secrets from the result
+#[derive(Debug)]
+struct RedactingEncoder(Box<dyn Encode>);
+
+impl Encode for RedactingEncoder {
+    fn encode(&self, w: &mut dyn encode::Write, record: &Record) -> anyhow::Result<()> {
+        let mut buffer = SimpleWriter(Vec::new());
+        self.0.encode(&mut buffer, record)?;
+
+        w.write_all(redact(&String::from_utf8_lossy(&buffer.0)).as_bytes())?;
+        Ok(())
+    }
+}
+
+/// a setting from its env variable, falling back to git config
+fn setting(env_var: &str, config_key: &str) -> Option<String> {
+    env::var(env_var).ok().or_else(|| {
+        // outside of a repository only the user and system config apply
+        let config = match repo::config() {
+            Ok(config) => config,
+            Err(_) => git2::Config::open_default().ok()?,
+        };
+
+        config.get_string(config_key).ok()
+    })
+}
+
+/// the default log file lives in the git dir so it doesn't end up in the working tree
+fn default_log_file() -> Option<PathBuf> {
+    repo::open()
+        .ok()
+        .map(|repo| repo::lfs_dir(&repo).join("logs").join("git-lfs-web3.log"))
+}
+
+fn text_encoder() -> Box<dyn Encode> {
+    Box::new(PatternEncoder::new("{d(%Y-%m-%dT%H:%M:%S%.3f%z)} {l} - {m}\n"))
+}
+
+fn stderr_appender(encoder: Box<dyn Encode>) -> Box<dyn Append> {
+    Box::new(
+        ConsoleAppender::builder()
+            .target(Target::Stderr)
+            .encoder(encoder)
+            .build()
+    )
+}
+
+fn install(appender: Box<dyn Append>, level: LevelFilter) -> Result<()> {
+    let config = Config::builder()
+        .appender(Appender::builder().build("log", appender))
+        .build(Root::builder()
+            .appender("log")
+            .build(level))?;
+
+    log4rs::init_config(config)?;
+
+    Ok(())
+}
+
+/// Set up logging as configured through env variables or git config.
+pub fn init() -> Result<()> {
+    for var in SECRET_ENV_VARS {
+        if let Ok(secret) = env::var(var) {
+            register_secret(&secret);
+        }
+    }
+
+    let level = match setting(LEVEL_ENV_VAR, LEVEL_CONFIG_KEY) {
+        Some(level) => LevelFilter::from_str(&level)
+            .map_err(|_| anyhow::anyhow!("invalid log level '{}'", level))?,
+        None => LevelFilter::Info,
+    };
+
+    let encoder: Box<dyn Encode> = match setting(FORMAT_ENV_VAR, FORMAT_CONFIG_KEY).as_deref() {
+        Some("json") => Box::new(JsonEncoder::new()),
+        Some("text") | None => text_encoder(),
+        Some(other) => return Err(anyhow::anyhow!("invalid log format '{}', expected text or json", other)),
+    };
+    let encoder = Box::new(RedactingEncoder(encoder));
+
+    let appender: Box<dyn Append> = match setting(DESTINATION_ENV_VAR, DESTINATION_CONFIG_KEY) {
+        Some(destination) if destination == "off" => return Ok(()),
+        Some(destination) if destination == "stderr" => stderr_appender(encoder),
+        Some(path) => Box::new(FileAppender::builder().encoder(encoder).build(path)?),
+        None => match default_log_file() {
+            Some(path) => Box::new(FileAppender::builder().encoder(encoder).build(path)?),
+            // outside of a repository there is nowhere sensible to put a log file
+            None => return Ok(()),
+        },
+    };
+
+    install(appender, level)
+}
+
+/// Log warnings and errors as text to stderr, for when the configured logging can't be set up.
+pub fn init_stderr() -> Result<()> {
+    install(stderr_appender(Box::new(RedactingEncoder(text_encoder()))), LevelFilter::Warn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn redact_scrubs_registered_secrets() {
+        register_secret("hunter2-api-key");
+        register_secret("abc");
+
+        assert_eq!(
+            redact("uploading with Skynet-Api-Key: hunter2-api-key to abc"),
+            "uploading with Skynet-Api-Key: [REDACTED] to abc"
+        );
+    }
+}
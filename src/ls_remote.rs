@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::pointer;
+use crate::provider::{RemoteObject, StorageProvider};
+use crate::repo;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Table,
+    Json,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            other => Err(anyhow::anyhow!("unknown format '{}', expected table, json or csv", other)),
+        }
+    }
+}
+
+/// A CSV record of the fields, ending in a newline.
+///
+/// Fields holding a comma, quote or line break are quoted, with quotes doubled, as in RFC 4180.
+pub fn csv_record<S: AsRef<str>>(fields: &[S]) -> String {
+    let mut record = fields
+        .iter()
+        .map(|field| {
+            let field = field.as_ref();
+
+            if field.contains(&[',', '"', '\r', '\n'][..]) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    record.push('\n');
+    record
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// stored and referenced from the history
+    Ok,
+    /// stored, but not referenced from any ref
+    Orphan,
+    /// referenced from the history, but not stored
+    Missing,
+}
+
+impl Status {
+    fn label(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Orphan => "orphan",
+            Status::Missing => "missing",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Row {
+    #[serde(flatten)]
+    pub object: RemoteObject,
+    pub status: Status,
+}
+
+/// List the objects held by the provider, cross-referenced with the LFS pointers of all refs.
+pub async fn ls_remote(client: &dyn StorageProvider) -> Result<Vec<Row>> {
+    if !client.capabilities().supports_list {
+        return Err(anyhow::anyhow!("{} can't list the objects it holds", client.name()));
+    }
+
+    let repo = repo::open()?;
+    let mut referenced = pointer::pointers_in_history(&repo, &[])?;
+
+    let mut rows: BTreeMap<String, Row> = BTreeMap::new();
+
+    for object in client.list().await? {
+        let status = match referenced.remove(&object.oid) {
+            Some(_) => Status::Ok,
+            None => Status::Orphan,
+        };
+
+        rows.insert(object.oid.clone(), Row { object, status });
+    }
+
+    for (oid, pointer) in referenced {
+        rows.insert(oid.clone(), Row {
+            object: RemoteObject {
+                oid,
+                size: Some(pointer.object.size),
+                uploaded_at: None,
+                address: None,
+            },
+            status: Status::Missing,
+        });
+    }
+
+    Ok(rows.into_values().collect())
+}
+
+pub fn render(rows: &[Row], format: Format) -> Result<String> {
+    let columns = |row: &Row| {
+        [
+            row.object.oid.clone(),
+            row.object.size.map(|size| size.to_string()).unwrap_or_default(),
+            row.object.uploaded_at.map(|time| time.to_rfc3339()).unwrap_or_default(),
+            row.object.address.clone().unwrap_or_default(),
+            row.status.label().to_string(),
+        ]
+    };
+
+    match format {
+        Format::Json => Ok(serde_json::to_string_pretty(rows)?),
+        Format::Csv => {
+            let mut csv = String::from("oid,size,uploaded_at,address,status\n");
+            for row in rows {
+                csv.push_str(&csv_record(&columns(row)));
+            }
+            Ok(csv)
+        }
+        Format::Table => {
+            let mut table = format!("{:<64}  {:>12}  {:<25}  {:<8}  {}\n", "OID", "SIZE", "UPLOADED", "STATUS", "ADDRESS");
+            for row in rows {
+                let [oid, size, uploaded_at, address, status] = columns(row);
+                table.push_str(&format!("{:<64}  {:>12}  {:<25}  {:<8}  {}\n", oid, size, uploaded_at, status, address));
+            }
+            Ok(table)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn render_csv_leaves_unknown_fields_empty() {
+        let rows = vec![
+            Row {
+                object: RemoteObject {
+                    oid: "aa".to_string(),
+                    size: Some(11),
+                    uploaded_at: None,
+                    address: Some("skylink".to_string()),
+                },
+                status: Status::Orphan,
+            },
+            Row {
+                object: RemoteObject {
+                    oid: "bb".to_string(),
+                    size: None,
+                    uploaded_at: None,
+                    address: None,
+                },
+                status: Status::Missing,
+            },
+        ];
+
+        assert_eq!(
+            render(&rows, Format::Csv).unwrap(),
+            "oid,size,uploaded_at,address,status\naa,11,,skylink,orphan\nbb,,,,missing\n"
+        );
+    }
+
+    #[test]
+    fn csv_record_quotes_special_fields() {
+        assert_eq!(csv_record(&["plain", "", "a,b"]), "plain,,\"a,b\"\n");
+        assert_eq!(csv_record(&["say \"hi\"", "two\nlines"]), "\"say \"\"hi\"\"\",\"two\nlines\"\n");
+    }
+}
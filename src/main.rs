@@ -7,11 +7,6 @@ use skynet_rs::{SkynetClient, SkynetClientOptions};
 use structopt::StructOpt;
 use tokio::io::{stdin, stdout, BufReader};
 
-use log::LevelFilter;
-use log4rs::append::file::FileAppender;
-use log4rs::encode::pattern::PatternEncoder;
-use log4rs::config::{Appender, Config, Root};
-
 use crate::providers::{SkynetProvider, StorJProvider, UploadStrategy};
 
 // use crate::{clean::clean, smudge::smudge};
@@ -21,6 +16,25 @@ use crate::providers::{SkynetProvider, StorJProvider, UploadStrategy};
 mod transfer;
 mod provider;
 mod providers;
+mod doctor;
+mod oid;
+mod pointer;
+mod verify;
+mod migrate;
+mod gc;
+mod ls_remote;
+mod renew;
+mod logging;
+mod credentials;
+mod repo;
+mod tmp;
+mod throttle;
+mod serve;
+mod locks;
+mod http;
+mod hooks;
+mod audit;
+mod upload_index;
 
 #[derive(Debug, StructOpt)]
 #[structopt(author, about)]
@@ -42,7 +56,123 @@ enum GitLfsIpfs {
     /// git-lfs custom transfer for IPFS
     ///
     /// <https://github.com/git-lfs/git-lfs/blob/main/docs/custom-transfers.md>
-    Transfer,
+    Transfer {
+        /// Only check which objects would be uploaded, also enabled by lfs-web3.dryrun
+        #[structopt(long)]
+        dry_run: bool,
+        /// Ask the provider about every object instead of trusting the upload index, also enabled by lfs-web3.uploadindex.revalidate
+        #[structopt(long)]
+        revalidate: bool,
+    },
+    /// Check the git-lfs setup, the provider configuration and connectivity
+    ///
+    /// Exits with a non-zero status when any check fails.
+    Doctor,
+    /// Copy all objects from one provider to another
+    ///
+    /// Objects already present on the target are skipped, so an interrupted migration can be run again.
+    Migrate {
+        /// Provider to copy the objects from
+        #[structopt(long)]
+        from: String,
+        /// Provider to copy the objects to
+        #[structopt(long)]
+        to: String,
+        /// How many objects to copy at the same time, defaults to what the target provider recommends
+        #[structopt(long)]
+        jobs: Option<usize>,
+        /// Keep using the current provider after the migration
+        #[structopt(long)]
+        no_switch: bool,
+    },
+    /// Delete objects from the provider that no commit references anymore
+    ///
+    /// Only reports what would be deleted unless --prune is given.
+    Gc {
+        /// Refs whose history keeps objects alive, defaults to lfs-web3.gc.ref or else all refs
+        #[structopt(long = "ref")]
+        refs: Vec<String>,
+        /// Keep unreferenced objects uploaded less than this many days ago
+        #[structopt(long, default_value = "14")]
+        grace_days: i64,
+        /// Delete the unreferenced objects instead of only reporting them
+        #[structopt(long)]
+        prune: bool,
+    },
+    /// List the objects held by the provider
+    ///
+    /// Objects are cross-referenced with the LFS pointers of all refs to flag orphaned and missing ones.
+    LsRemote {
+        /// Output format: table, json or csv
+        #[structopt(long, default_value = "table")]
+        format: ls_remote::Format,
+    },
+    /// Show the transfers recorded in the audit log
+    ///
+    /// Filters can be combined, only records matching all of them are shown.
+    Audit {
+        /// Only transfers of objects whose oid starts with this
+        #[structopt(long)]
+        oid: Option<String>,
+        /// Only uploads or downloads
+        #[structopt(long, possible_values = &["upload", "download"])]
+        operation: Option<String>,
+        /// Only transfers by users whose name or email contains this
+        #[structopt(long)]
+        user: Option<String>,
+        /// Only transfers for this git remote
+        #[structopt(long)]
+        remote: Option<String>,
+        /// Only transfers since this date (YYYY-MM-DD) or RFC 3339 time
+        #[structopt(long, parse(try_from_str = audit::parse_since))]
+        since: Option<chrono::DateTime<chrono::Utc>>,
+        /// Only failed transfers
+        #[structopt(long)]
+        failed: bool,
+        /// Output format: table, json or csv
+        #[structopt(long, default_value = "table")]
+        format: ls_remote::Format,
+    },
+    /// Re-pin skylinks before the portal unpins them
+    ///
+    /// Skylinks that can't be pinned anymore are uploaded again from the local copy.
+    Renew {
+        /// Renew skylinks expiring within this many days, defaults to lfs-web3.skynet.renewdays or 14
+        #[structopt(long)]
+        days: Option<i64>,
+        /// Only list the skylinks that are due for renewal
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Publish the OID => Skylink mappings under the repository's Skynet registry entry
+    ///
+    /// Creates the registry keypair on first use and prints the public key downloaders need.
+    Registry,
+    /// Serve the Git LFS Batch API over HTTP, storing objects with the configured provider
+    ///
+    /// Point `lfs.url` at it for tools that can't use a custom transfer agent.
+    Serve {
+        /// Address to listen on, only loopback addresses are allowed
+        #[structopt(long, default_value = "127.0.0.1:8080")]
+        listen: std::net::SocketAddr,
+    },
+    /// Check that every LFS object in the history can be retrieved from the provider
+    ///
+    /// Exits with a non-zero status when any object is missing, unmapped or corrupt.
+    Verify {
+        /// Revisions whose history to check
+        #[structopt(default_value = "HEAD")]
+        revs: Vec<String>,
+        /// Check the history of all refs instead of the given revisions
+        #[structopt(long)]
+        all_refs: bool,
+        /// Also download every object and check its hash
+        #[structopt(long)]
+        deep: bool,
+        /// Print the report as JSON
+        #[structopt(long)]
+        json: bool,
+    },
 }
 
 #[tokio::main]
@@ -54,36 +184,50 @@ async fn main() -> Result<()> {
 }
 
 async fn main_inner() -> Result<()> {
-    let logfile = FileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new("{l} - {m}\n")))
-        .build("log/output.log")?;
+    // parse first, so --help and usage errors don't depend on the log configuration
+    let command = GitLfsIpfs::from_args();
 
-    let config = Config::builder()
-        .appender(Appender::builder().build("logfile", Box::new(logfile)))
-        .build(Root::builder()
-            .appender("logfile")
-            .build(LevelFilter::Debug))?;
+    if let Err(err) = logging::init() {
+        eprintln!("warning: could not set up logging, logging to stderr instead: {:#}", err);
+        logging::init_stderr()?;
+    }
 
-    log4rs::init_config(config)?;
+    http::HttpSettings::from_config().export_env();
 
     log::debug!("pwd: {}", env::current_dir().unwrap().display());
 
-    // todo: dynamic client choice here based on ENV var?
-    // let client = StorJProvider::new_from_env()?;
-    let client = SkynetProvider::new_from_env(UploadStrategy::Client)?;
-
-    match GitLfsIpfs::from_args() {
+    match command {
         // GitLfsIpfs::Smudge { filename: _ } => smudge(client, stdin(), stdout()).await,
         // GitLfsIpfs::Clean { filename: _ } => clean(client, std::io::stdin(), stdout()).await,
-        GitLfsIpfs::Transfer => {
+        GitLfsIpfs::Transfer { dry_run, revalidate } => {
+            if let Err(err) = tmp::clean_stale() {
+                log::warn!("could not clean up stale partial downloads: {:#}", err);
+            }
+
+            let client = upload_index::Indexed::from_config(providers::from_config()?, revalidate);
+
+            let dry_run = dry_run || repo::config()
+                .and_then(|config| Ok(config.get_bool(transfer::DRY_RUN_CONFIG_KEY)?))
+                .unwrap_or(false);
+
+            if dry_run {
+                log::info!("dry run: nothing will be uploaded");
+            }
+
             let buffered_stdin = BufReader::new(stdin());
 
             // the input stream of events passed by git-lfs
             let input_event_stream = transfer::read_events(buffered_stdin);
 
             // the output stream we are writing back to the console for git-lfs to read
-            let output_event_stream =
-                transfer::transfer(client, input_event_stream);
+            let output_event_stream = transfer::transfer(
+                client,
+                input_event_stream,
+                dry_run,
+                hooks::Hooks::from_config(),
+                audit::AuditLog::from_config(),
+                transfer::local_uploads,
+            );
 
             futures_util::pin_mut!(output_event_stream);
 
@@ -98,5 +242,73 @@ async fn main_inner() -> Result<()> {
             }
             Ok(())
         }
+        GitLfsIpfs::Verify { revs, all_refs, deep, json } => {
+            let client = providers::from_config()?;
+            let revs = if all_refs { vec![] } else { revs };
+
+            let report = verify::verify(&*client, &revs, deep).await?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                verify::print(&report);
+            }
+
+            if !report.is_ok() {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        GitLfsIpfs::Serve { listen } => serve::serve(listen).await,
+        GitLfsIpfs::Registry => {
+            let client = providers::SkynetProvider::new_from_env(providers::UploadStrategy::Client)?;
+            let public_key = client.init_registry().await?;
+
+            println!("published the mapping index under public key {}", public_key);
+            println!("to resolve objects from it in another clone, run:");
+            println!("  git config lfs-web3.skynet.registry.publickey {}", public_key);
+            Ok(())
+        }
+        GitLfsIpfs::Migrate { from, to, jobs, no_switch } => {
+            if !migrate::migrate(&from, &to, jobs, !no_switch).await? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        GitLfsIpfs::Gc { refs, grace_days, prune } => {
+            // pruned objects are removed from the upload index too
+            let client = upload_index::Indexed::from_config(providers::from_config()?, false);
+
+            if !gc::gc(&client, refs, grace_days, prune).await? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        GitLfsIpfs::LsRemote { format } => {
+            let client = providers::from_config()?;
+
+            let rows = ls_remote::ls_remote(&*client).await?;
+            print!("{}", ls_remote::render(&rows, format)?);
+            Ok(())
+        }
+        GitLfsIpfs::Audit { oid, operation, user, remote, since, failed, format } => {
+            let filter = audit::Filter { oid, operation, user, remote, since, failed };
+
+            let records = audit::query(&audit::AuditLog::from_config(), &filter)?;
+            print!("{}", audit::render(&records, format)?);
+            Ok(())
+        }
+        GitLfsIpfs::Renew { days, dry_run } => {
+            if !renew::renew(days, dry_run).await? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        GitLfsIpfs::Doctor => {
+            if !doctor::doctor().await? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use git_lfs_spec::Object;
+use git_lfs_spec::transfer::custom::{Download, Upload};
+use tokio::io::AsyncWriteExt;
+
+use crate::oid::{oid_of_file, VerifyingReader};
+use crate::pointer;
+use crate::provider::StorageProvider;
+use crate::providers;
+use crate::repo;
+
+/// outcome of migrating a single object
+enum Outcome {
+    Copied(u64),
+    AlreadyPresent,
+    Failed(anyhow::Error),
+}
+
+/// Copy every known object from the `from` provider to the `to` provider.
+///
+/// Objects the target already has are skipped, so an interrupted migration can simply be run again.
+/// When every object made it across and `switch` is set, the repository is configured to use the
+/// target provider from now on.
+pub async fn migrate(from: &str, to: &str, jobs: Option<usize>, switch: bool) -> Result<bool> {
+    if from == to {
+        return Err(anyhow::anyhow!("source and target provider are both '{}'", from));
+    }
+
+    let source = providers::from_name(from).with_context(|| format!("could not set up {}", from))?;
+    let target = providers::from_name(to).with_context(|| format!("could not set up {}", to))?;
+
+    let repo = repo::open()?;
+    let objects = known_objects(&repo, &*source).await?;
+    let jobs = jobs.unwrap_or_else(|| target.capabilities().recommended_concurrency);
+
+    println!("migrating {} objects from {} to {}", objects.len(), from, to);
+
+    // without a known size the target can't be asked yet, those are checked after downloading
+    let checkable: Vec<Upload> = objects
+        .iter()
+        .filter(|object| object.size > 0)
+        .map(|object| Upload {
+            object: object.clone(),
+            path: pointer::local_object_path(&repo, &object.oid),
+        })
+        .collect();
+
+    let on_target = target.is_uploaded_many(&checkable).await.unwrap_or_else(|err| {
+        log::warn!("batch existence check on {} failed: {:#}", to, err);
+        Default::default()
+    });
+
+    let (objects, present_before): (Vec<Object>, Vec<Object>) = objects
+        .into_iter()
+        .partition(|object| on_target.get(&object.oid) != Some(&true));
+
+    let outcomes: Vec<(String, Outcome)> = futures::stream::iter(objects)
+        .map(|object| {
+            let (source, target) = (&*source, &*target);
+
+            async move {
+                let oid = object.oid.clone();
+                (oid, migrate_object(source, target, object).await)
+            }
+        })
+        .buffer_unordered(jobs.max(1))
+        .collect()
+        .await;
+
+    let mut copied = 0;
+    let mut bytes = 0;
+    let mut present = present_before.len();
+    let mut failed = 0;
+
+    for (oid, outcome) in outcomes {
+        match outcome {
+            Outcome::Copied(size) => {
+                copied += 1;
+                bytes += size;
+            }
+            Outcome::AlreadyPresent => present += 1,
+            Outcome::Failed(err) => {
+                failed += 1;
+                println!("failed {}: {:#}", oid, err);
+            }
+        }
+    }
+
+    println!(
+        "copied {} objects ({} bytes), {} already present, {} failed",
+        copied, bytes, present, failed
+    );
+
+    if failed > 0 {
+        println!("run the migration again to retry the failed objects");
+        return Ok(false);
+    }
+
+    if switch {
+        repo.config()?
+            .open_level(git2::ConfigLevel::Local)?
+            .set_str(providers::PROVIDER_CONFIG_KEY, to)?;
+
+        println!("set {} to {}", providers::PROVIDER_CONFIG_KEY, to);
+    }
+
+    Ok(true)
+}
+
+/// the objects the source provider is known to hold: its mappings if it needs them, else the local objects
+async fn known_objects(repo: &git2::Repository, source: &dyn StorageProvider) -> Result<Vec<Object>> {
+    let mut objects: BTreeMap<String, Object> = pointer::local_objects(repo)?
+        .into_iter()
+        .map(|object| (object.oid.clone(), object))
+        .collect();
+
+    if source.capabilities().needs_mapping {
+        let mapped = source.list().await?;
+
+        // sizes are only known for objects with a local copy, the rest is left at 0, which
+        // downloads take as unknown, and measured after downloading
+        objects = mapped
+            .into_iter()
+            .map(|remote| {
+                let object = objects.remove(&remote.oid).unwrap_or(Object {
+                    oid: remote.oid.clone(),
+                    size: remote.size.unwrap_or(0),
+                });
+                (remote.oid, object)
+            })
+            .collect();
+    }
+
+    Ok(objects.into_values().collect())
+}
+
+/// buffer between the download from the source and the upload to the target
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+async fn migrate_object(
+    source: &dyn StorageProvider,
+    target: &dyn StorageProvider,
+    object: Object,
+) -> Outcome {
+    // an object of unknown size can only be verified and announced to the target once it's downloaded
+    let result = if object.size > 0 {
+        stream_object(source, target, object).await
+    } else {
+        copy_via_file(source, target, object).await
+    };
+
+    match result {
+        Ok(Some(size)) => Outcome::Copied(size),
+        Ok(None) => Outcome::AlreadyPresent,
+        Err(err) => Outcome::Failed(err),
+    }
+}
+
+/// Stream an object from the source straight into an upload to the target.
+///
+/// The upload reads the object through a `VerifyingReader`, so bytes that don't hash to the
+/// oid fail the upload before it completes.
+async fn stream_object(source: &dyn StorageProvider, target: &dyn StorageProvider, object: Object) -> Result<Option<u64>> {
+    let upload = Upload {
+        object: object.clone(),
+        path: PathBuf::new(),
+    };
+
+    if target.is_uploaded(&upload).await.context("existence check on target failed")? {
+        return Ok(None);
+    }
+
+    let (mut writer, reader) = tokio::io::duplex(STREAM_BUFFER_SIZE);
+
+    // the writer is dropped when the download ends, which ends the upload's input even on failure
+    let download = async move {
+        source.download_writer(&object, &mut writer).await?;
+        writer.shutdown().await?;
+        Ok::<_, anyhow::Error>(())
+    };
+    let upload_object = target.upload_reader(&upload.object, Box::new(VerifyingReader::new(reader, &upload.object)));
+
+    let (downloaded, uploaded) = futures::join!(download, upload_object);
+
+    // a failed download makes the upload fail as well, so its error is the one to report
+    downloaded.context("download from source failed")?;
+    uploaded.context("upload to target failed")?;
+
+    Ok(Some(upload.object.size))
+}
+
+async fn copy_via_file(source: &dyn StorageProvider, target: &dyn StorageProvider, object: Object) -> Result<Option<u64>> {
+    let downloaded = source
+        .download(&Download { object: object.clone() })
+        .await
+        .context("download from source failed")?;
+
+    let result = copy_downloaded(target, &object.oid, &downloaded).await;
+
+    let _ = tokio::fs::remove_file(&downloaded).await;
+
+    result
+}
+
+/// check the downloaded file and upload it to the target, unless the target turns out to have it
+async fn copy_downloaded(target: &dyn StorageProvider, oid: &str, downloaded: &str) -> Result<Option<u64>> {
+    let actual_oid = oid_of_file(downloaded).await?;
+
+    if actual_oid != oid {
+        return Err(anyhow::anyhow!("source returned an object hashing to {}", actual_oid));
+    }
+
+    let upload = Upload {
+        object: Object {
+            oid: oid.to_string(),
+            size: tokio::fs::metadata(downloaded).await?.len(),
+        },
+        path: downloaded.into(),
+    };
+
+    if target.is_uploaded(&upload).await.context("existence check on target failed")? {
+        return Ok(None);
+    }
+
+    target.upload(&upload).await.context("upload to target failed")?;
+
+    Ok(Some(upload.object.size))
+}
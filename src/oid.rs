@@ -0,0 +1,146 @@
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use anyhow::Result;
+use futures::ready;
+use git_lfs_spec::Object;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+use crate::tmp::Mismatch;
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// git-lfs object id (hex encoded SHA-256) of some bytes
+pub fn oid_of_bytes(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// git-lfs object id (hex encoded SHA-256) of a file's contents
+pub async fn oid_of_file(path: impl AsRef<Path>) -> Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut hasher = Sha256::new();
+
+    loop {
+        let bytes_read = file.read(&mut buffer).await?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Hashes everything read through it, and fails instead of returning the last bytes or EOF
+/// when the bytes read aren't the object.
+///
+/// The check runs as soon as the object's size is reached, as readers of sized bodies may
+/// never ask for EOF.
+pub struct VerifyingReader<R> {
+    inner: R,
+    object: Object,
+    hasher: Sha256,
+    size: u64,
+    verified: bool,
+}
+
+impl<R> VerifyingReader<R> {
+    pub fn new(inner: R, object: &Object) -> Self {
+        VerifyingReader {
+            inner,
+            object: object.clone(),
+            hasher: Sha256::new(),
+            size: 0,
+            verified: false,
+        }
+    }
+
+    fn verify(&mut self) -> io::Result<()> {
+        let oid = hex::encode(std::mem::take(&mut self.hasher).finalize());
+
+        if self.size != self.object.size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, Mismatch(format!(
+                "expected {} bytes, read {}", self.object.size, self.size
+            ))));
+        }
+
+        if oid != self.object.oid {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, Mismatch(format!(
+                "expected oid {}, read bytes hashing to {}", self.object.oid, oid
+            ))));
+        }
+
+        self.verified = true;
+        Ok(())
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for VerifyingReader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if buf.remaining() == 0 {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let read = &buf.filled()[before..];
+
+        if read.is_empty() {
+            return Poll::Ready(if this.verified { Ok(()) } else { this.verify() });
+        }
+
+        this.size += read.len() as u64;
+        this.hasher.update(read);
+
+        if this.size >= this.object.size {
+            return Poll::Ready(this.verify());
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+
+    const FILE: &[u8] = b"hello world";
+    const OID: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+    #[test]
+    fn oid_of_bytes_returns_sha256() {
+        assert_eq!(oid_of_bytes(FILE), OID);
+    }
+
+    #[tokio::test]
+    async fn oid_of_file_matches_oid_of_bytes() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(FILE).unwrap();
+
+        assert_eq!(oid_of_file(file.path()).await.unwrap(), OID);
+    }
+
+    async fn read_verified(bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let object = Object { oid: OID.to_string(), size: FILE.len() as u64 };
+        let mut read = vec![];
+
+        VerifyingReader::new(bytes, &object).read_to_end(&mut read).await?;
+
+        Ok(read)
+    }
+
+    #[tokio::test]
+    async fn verifying_reader_fails_at_the_end_on_other_bytes() {
+        assert_eq!(read_verified(FILE).await.unwrap(), FILE);
+        assert_eq!(read_verified(b"hello worle").await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(read_verified(b"hello").await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(read_verified(b"hello world!").await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use anyhow::{Context, Result};
+use git2::{ObjectType, Repository, TreeWalkMode, TreeWalkResult};
+use git_lfs_spec::Object;
+
+use crate::repo;
+
+/// git-lfs pointer files are never larger than this
+///
+/// <https://github.com/git-lfs/git-lfs/blob/main/docs/spec.md>
+const MAX_POINTER_SIZE: usize = 1024;
+
+const VERSION_PREFIX: &str = "version https://git-lfs.github.com/spec/";
+
+/// an LFS pointer found in the history, with the first path it was seen at
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pointer {
+    pub object: Object,
+    pub path: String,
+}
+
+/// Parse the contents of a git-lfs pointer file.
+///
+/// Files whose oid isn't a sha256 are not taken as pointers, oids are used in paths.
+pub fn parse(contents: &[u8]) -> Option<Object> {
+    if contents.len() > MAX_POINTER_SIZE {
+        return None;
+    }
+
+    let text = std::str::from_utf8(contents).ok()?;
+    let mut lines = text.lines();
+
+    if !lines.next()?.starts_with(VERSION_PREFIX) {
+        return None;
+    }
+
+    let mut oid = None;
+    let mut size = None;
+
+    for line in lines {
+        if let Some(hash) = line.strip_prefix("oid sha256:") {
+            oid = Some(hash.trim().to_string()).filter(|hash| is_oid(hash));
+        } else if let Some(len) = line.strip_prefix("size ") {
+            size = len.trim().parse().ok();
+        }
+    }
+
+    Some(Object {
+        oid: oid?,
+        size: size?,
+    })
+}
+
+/// whether `oid` is a sha256 as git-lfs writes it, 64 lowercase hex characters
+pub fn is_oid(oid: &str) -> bool {
+    oid.len() == 64 && oid.bytes().all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// where git-lfs keeps the local copy of an object
+pub fn local_object_path(repo: &Repository, oid: &str) -> PathBuf {
+    repo::lfs_dir(repo)
+        .join("objects")
+        .join(&oid[0..2])
+        .join(&oid[2..4])
+        .join(oid)
+}
+
+/// every object git-lfs has a local copy of
+pub fn local_objects(repo: &Repository) -> Result<Vec<Object>> {
+    let objects_dir = repo::lfs_dir(repo).join("objects");
+    let mut objects = vec![];
+
+    if !objects_dir.is_dir() {
+        return Ok(objects);
+    }
+
+    for first in std::fs::read_dir(&objects_dir)? {
+        let first = first?.path();
+        if !first.is_dir() {
+            continue;
+        }
+
+        for second in std::fs::read_dir(&first)? {
+            let second = second?.path();
+            if !second.is_dir() {
+                continue;
+            }
+
+            for file in std::fs::read_dir(&second)? {
+                let file = file?;
+                let metadata = file.metadata()?;
+
+                if metadata.is_file() {
+                    objects.push(Object {
+                        oid: file.file_name().to_string_lossy().to_string(),
+                        size: metadata.len(),
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(objects)
+}
+
+/// Collect every LFS pointer reachable from the given revisions, or from all refs when none are given.
+///
+/// The result is keyed by oid so every object is listed once.
+pub fn pointers_in_history(repo: &Repository, revs: &[String]) -> Result<BTreeMap<String, Pointer>> {
+    let mut revwalk = repo.revwalk()?;
+
+    if revs.is_empty() {
+        revwalk.push_glob("*")?;
+    } else {
+        for rev in revs {
+            let commit = repo
+                .revparse_single(rev)
+                .and_then(|object| object.peel_to_commit())
+                .with_context(|| format!("could not resolve {}", rev))?;
+
+            revwalk.push(commit.id())?;
+        }
+    }
+
+    let odb = repo.odb()?;
+    let mut seen = HashSet::new();
+    let mut pointers = BTreeMap::new();
+
+    for commit_id in revwalk {
+        let tree = repo.find_commit(commit_id?)?.tree()?;
+
+        if !seen.insert(tree.id()) {
+            continue;
+        }
+
+        let mut error = None;
+
+        let walked = tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+            // identical subtrees and blobs only need to be looked at once
+            if !seen.insert(entry.id()) {
+                return TreeWalkResult::Skip;
+            }
+
+            if entry.kind() != Some(ObjectType::Blob) {
+                return TreeWalkResult::Ok;
+            }
+
+            let result = odb.read_header(entry.id()).and_then(|(size, _)| {
+                if size > MAX_POINTER_SIZE {
+                    return Ok(None);
+                }
+                repo.find_blob(entry.id()).map(|blob| parse(blob.content()))
+            });
+
+            match result {
+                Ok(Some(object)) => {
+                    pointers.entry(object.oid.clone()).or_insert_with(|| Pointer {
+                        object,
+                        path: format!("{}{}", root, entry.name().unwrap_or_default()),
+                    });
+                    TreeWalkResult::Ok
+                }
+                Ok(None) => TreeWalkResult::Ok,
+                Err(err) => {
+                    error = Some(err);
+                    TreeWalkResult::Abort
+                }
+            }
+        });
+
+        if let Some(err) = error {
+            return Err(err.into());
+        }
+
+        walked?;
+    }
+
+    Ok(pointers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::path::Path;
+
+    const POINTER: &[u8] = b"version https://git-lfs.github.com/spec/v1
+oid sha256:b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9
+size 11
+";
+    const OID: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+    #[test]
+    fn parse_reads_oid_and_size() {
+        assert_eq!(
+            parse(POINTER),
+            Some(Object {
+                oid: OID.to_string(),
+                size: 11,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_ignores_regular_files() {
+        assert_eq!(parse(b"hello world"), None);
+        assert_eq!(parse(b"version https://git-lfs.github.com/spec/v1\nsize 11\n"), None);
+    }
+
+    #[test]
+    fn parse_rejects_malformed_oids() {
+        let pointer = |oid: &str| format!("version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 11\n", oid);
+
+        assert_eq!(parse(pointer("abc").as_bytes()), None);
+        assert_eq!(parse(pointer(&OID.to_uppercase()).as_bytes()), None);
+        assert_eq!(parse(pointer(&format!("{}0", OID)).as_bytes()), None);
+        assert_eq!(parse(pointer(&OID.replace('b', "g")).as_bytes()), None);
+        assert!(parse(pointer(OID).as_bytes()).is_some());
+    }
+
+    #[test]
+    fn pointers_in_history_finds_committed_pointers() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+
+        std::fs::create_dir(dir.path().join("assets")).unwrap();
+        std::fs::write(dir.path().join("assets/file.bin"), POINTER).unwrap();
+        std::fs::write(dir.path().join("README"), "not a pointer").unwrap();
+
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("assets/file.bin")).unwrap();
+        index.add_path(Path::new("README")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("test", "test@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[]).unwrap();
+
+        let pointers = pointers_in_history(&repo, &["HEAD".to_string()]).unwrap();
+
+        assert_eq!(pointers.len(), 1);
+        assert_eq!(pointers[OID].path, "assets/file.bin");
+        assert_eq!(pointers[OID].object.size, 11);
+    }
+}
@@ -1,15 +1,160 @@
+use std::collections::HashMap;
 use std::future::Future;
 use std::path::Path;
 use anyhow::{Context, Result};
+use futures::StreamExt;
 use git_lfs_spec::transfer::custom::*;
+use git_lfs_spec::Object;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// what a provider can and can't do, so callers adapt to it instead of checking its name
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Capabilities {
+    /// objects are content-addressed, so the oid => address mapping has to be kept
+    pub needs_mapping: bool,
+    /// objects can be downloaded in ranges, so interrupted downloads can be resumed
+    pub supports_range: bool,
+    pub supports_delete: bool,
+    pub supports_list: bool,
+    /// the listing only holds objects of this repository, so unreferenced ones are garbage
+    pub scoped_list: bool,
+    /// the largest object the provider accepts, if it has a limit
+    pub max_object_size: Option<u64>,
+    /// how many requests to run at the same time
+    pub recommended_concurrency: usize,
+}
+
+impl Default for Capabilities {
+    /// a key-addressed store that can only upload and download whole objects
+    fn default() -> Self {
+        Self {
+            needs_mapping: false,
+            supports_range: false,
+            supports_delete: false,
+            supports_list: false,
+            scoped_list: false,
+            max_object_size: None,
+            recommended_concurrency: 4,
+        }
+    }
+}
+
+/// metadata key the oid of an object is stored under
+pub const OID_METADATA: &str = "oid";
+
+/// metadata key the size of an object is stored under
+pub const SIZE_METADATA: &str = "size";
+
+/// Whether the size and metadata a provider holds for an object match the object.
+///
+/// Objects uploaded before the metadata was recorded only have their size checked.
+pub fn matches_stored(object: &Object, size: Option<u64>, metadata: &HashMap<String, String>) -> bool {
+    let mut mismatches = vec![];
+
+    if let Some(size) = size.filter(|size| *size != object.size) {
+        mismatches.push(format!("{} bytes", size));
+    }
+    if let Some(oid) = metadata.get(OID_METADATA).filter(|oid| *oid != &object.oid) {
+        mismatches.push(format!("oid {}", oid));
+    }
+    if let Some(recorded) = metadata.get(SIZE_METADATA).filter(|recorded| *recorded != &object.size.to_string()) {
+        mismatches.push(format!("recorded size {}", recorded));
+    }
+
+    if !mismatches.is_empty() {
+        log::warn!(
+            "stored object for {} doesn't match, it has {} instead of {} bytes",
+            &object.oid, mismatches.join(", "), object.size
+        );
+    }
+
+    mismatches.is_empty()
+}
+
+/// the content of an object being uploaded
+pub type ObjectReader = Box<dyn AsyncRead + Send + Sync + Unpin>;
+
+/// an object as held by a provider
+#[derive(Clone, Debug, Serialize)]
+pub struct RemoteObject {
+    pub oid: String,
+    pub size: Option<u64>,
+    pub uploaded_at: Option<DateTime<Utc>>,
+    /// skylink or key the object is stored under
+    pub address: Option<String>,
+}
 
 #[async_trait]
 pub trait StorageProvider : Sync {
-    async fn download(&self, obj: &Download) -> anyhow::Result<String>;
-    async fn upload(&self, obj: &Upload) -> anyhow::Result<()>;
+    /// short name of the provider, as used in `lfs-web3.provider`
+    fn name(&self) -> &'static str;
+
+    /// Where the provider stores objects, such as its endpoint and bucket.
+    ///
+    /// What is known about the objects a provider holds is kept per target, so pointing the
+    /// provider somewhere else doesn't make objects look uploaded there.
+    fn target(&self) -> String {
+        self.name().to_string()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// upload an object, reading its `size` bytes from `reader`
+    async fn upload_reader(&self, object: &Object, reader: ObjectReader) -> anyhow::Result<()>;
+
+    /// Write the content of an object to `writer`.
+    ///
+    /// The content isn't checked against the oid, that is up to the caller.
+    async fn download_writer(&self, object: &Object, writer: &mut (dyn AsyncWrite + Send + Unpin)) -> anyhow::Result<()>;
+
+    /// download an object into the temp dir, returning the path of the verified file
+    async fn download(&self, obj: &Download) -> anyhow::Result<String> {
+        let partial = crate::tmp::PartialDownload::new(&obj.object)?;
+        // without range requests a partial download can't be continued
+        partial.restart()?;
+
+        let mut file = partial.tokio_file()?;
+        self.download_writer(&obj.object, &mut file).await?;
+        file.flush().await?;
+
+        Ok(partial.complete().await?.to_string_lossy().to_string())
+    }
+
+    async fn upload(&self, obj: &Upload) -> anyhow::Result<()> {
+        let file = tokio::fs::File::open(&obj.path)
+            .await
+            .with_context(|| format!("could not open {}", obj.path.display()))?;
+
+        self.upload_reader(&obj.object, Box::new(file)).await
+    }
+
     async fn is_uploaded(&self, obj: &Upload) -> anyhow::Result<bool>;
 
+    /// Check many objects at once, by oid.
+    ///
+    /// Objects whose check failed are reported as not uploaded.
+    async fn is_uploaded_many(&self, objs: &[Upload]) -> anyhow::Result<HashMap<String, bool>> {
+        let jobs = self.capabilities().recommended_concurrency * 2;
+        Ok(is_uploaded_concurrently(self, objs, jobs).await)
+    }
+
+    /// every object the provider holds for this repository
+    async fn list(&self) -> anyhow::Result<Vec<RemoteObject>>;
+
+    /// remove the object from storage, succeeding if it was already gone
+    async fn delete(&self, oid: &str) -> anyhow::Result<()>;
+
+    /// where the object for an oid is stored, or `None` if the provider doesn't know it.
+    /// key-addressed providers store objects under their oid
+    fn address(&self, oid: &str) -> Option<String> {
+        Some(oid.to_string())
+    }
+
     async fn upload_if_needed(&self, obj: &Upload) -> anyhow::Result<()> {
         match self.is_uploaded(obj).await {
             Ok(true) => {
@@ -19,8 +164,138 @@ pub trait StorageProvider : Sync {
         }
     }
 
-    fn git_config() -> git2::Config {
-        git2::Config::open(&Path::new(".git/config"))
-            .expect("failed to open git config file")
+    /// where the provider keeps Git LFS locks, if it can hold them
+    fn lock_store(&self) -> Option<&dyn crate::locks::LockStore> {
+        None
+    }
+
+    /// called once a batch of transfers or deletions is done, to publish state that is shared
+    async fn finish(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn git_config() -> Result<git2::Config> where Self: Sized {
+        crate::repo::config().context("failed to open git config of the repository")
+    }
+}
+
+/// run `is_uploaded` for every object, `jobs` at a time
+pub async fn is_uploaded_concurrently<P: StorageProvider + ?Sized>(
+    provider: &P,
+    objs: &[Upload],
+    jobs: usize,
+) -> HashMap<String, bool> {
+    futures::stream::iter(objs)
+        .map(|obj| async move {
+            let uploaded = provider.is_uploaded(obj).await.unwrap_or_else(|err| {
+                log::warn!("existence check for {} failed: {:#}", &obj.object.oid, err);
+                false
+            });
+
+            (obj.object.oid.clone(), uploaded)
+        })
+        .buffer_unordered(jobs.max(1))
+        .collect()
+        .await
+}
+
+/// lets a provider that was chosen at runtime be used wherever a `StorageProvider` is expected
+#[async_trait]
+impl<P: StorageProvider + ?Sized> StorageProvider for Box<P> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn target(&self) -> String {
+        (**self).target()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        (**self).capabilities()
+    }
+
+    async fn upload_reader(&self, object: &Object, reader: ObjectReader) -> anyhow::Result<()> {
+        (**self).upload_reader(object, reader).await
+    }
+
+    async fn download_writer(&self, object: &Object, writer: &mut (dyn AsyncWrite + Send + Unpin)) -> anyhow::Result<()> {
+        (**self).download_writer(object, writer).await
+    }
+
+    async fn download(&self, obj: &Download) -> anyhow::Result<String> {
+        (**self).download(obj).await
+    }
+
+    async fn upload(&self, obj: &Upload) -> anyhow::Result<()> {
+        (**self).upload(obj).await
+    }
+
+    async fn is_uploaded(&self, obj: &Upload) -> anyhow::Result<bool> {
+        (**self).is_uploaded(obj).await
+    }
+
+    async fn is_uploaded_many(&self, objs: &[Upload]) -> anyhow::Result<HashMap<String, bool>> {
+        (**self).is_uploaded_many(objs).await
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<RemoteObject>> {
+        (**self).list().await
+    }
+
+    async fn delete(&self, oid: &str) -> anyhow::Result<()> {
+        (**self).delete(oid).await
+    }
+
+    fn address(&self, oid: &str) -> Option<String> {
+        (**self).address(oid)
+    }
+
+    async fn upload_if_needed(&self, obj: &Upload) -> anyhow::Result<()> {
+        (**self).upload_if_needed(obj).await
+    }
+
+    fn lock_store(&self) -> Option<&dyn crate::locks::LockStore> {
+        (**self).lock_store()
+    }
+
+    async fn finish(&self) -> anyhow::Result<()> {
+        (**self).finish().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OID: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+    fn object() -> Object {
+        Object {
+            oid: OID.to_string(),
+            size: 11,
+        }
+    }
+
+    #[test]
+    fn matches_stored_rejects_truncated_or_mislabelled_objects() {
+        let metadata: HashMap<String, String> = vec![
+            (OID_METADATA.to_string(), OID.to_string()),
+            (SIZE_METADATA.to_string(), "11".to_string()),
+        ]
+        .into_iter()
+        .collect();
+
+        assert!(matches_stored(&object(), Some(11), &metadata));
+        assert!(!matches_stored(&object(), Some(5), &metadata));
+
+        let mut other = metadata.clone();
+        other.insert(OID_METADATA.to_string(), "a948904f".to_string());
+        assert!(!matches_stored(&object(), Some(11), &other));
+    }
+
+    #[test]
+    fn matches_stored_accepts_objects_without_metadata() {
+        assert!(matches_stored(&object(), Some(11), &HashMap::new()));
+        assert!(matches_stored(&object(), None, &HashMap::new()));
     }
-}
\ No newline at end of file
+}
@@ -1,5 +1,62 @@
+mod sia;
 mod skynet;
+mod skynet_registry;
 mod storj;
 
+pub use sia::*;
 pub use skynet::*;
-pub use storj::*;
\ No newline at end of file
+pub use storj::*;
+
+use std::env;
+use anyhow::Result;
+use crate::credentials::CredentialSpec;
+use crate::provider::StorageProvider;
+use crate::repo;
+
+/// names of the providers that can be selected through `lfs-web3.provider`
+pub const PROVIDERS: &[&str] = &["skynet", "storj", "sia"];
+
+/// git config key holding the name of the provider to use
+pub const PROVIDER_CONFIG_KEY: &str = "lfs-web3.provider";
+
+/// environment variable that overrides `lfs-web3.provider`
+pub const PROVIDER_ENV_VAR: &str = "GIT_LFS_WEB3_PROVIDER";
+
+/// which settings a provider reads and which of them are credentials
+pub fn credential_spec(name: &str) -> Option<&'static CredentialSpec> {
+    match name {
+        "skynet" => Some(&SKYNET_CREDENTIALS),
+        "storj" => Some(&STORJ_CREDENTIALS),
+        "sia" => Some(&SIA_CREDENTIALS),
+        _ => None
+    }
+}
+
+/// instantiate a provider by its name
+pub fn from_name(name: &str) -> Result<Box<dyn StorageProvider + Send>> {
+    match name {
+        "skynet" => Ok(Box::new(SkynetProvider::new_from_env(UploadStrategy::Client)?)),
+        "storj" => Ok(Box::new(StorJProvider::new_from_env()?)),
+        "sia" => Ok(Box::new(SiaProvider::new_from_env()?)),
+        other => Err(anyhow::anyhow!(
+            "unknown provider '{}', expected one of: {}", other, PROVIDERS.join(", ")
+        ))
+    }
+}
+
+/// name of the configured provider, defaulting to Skynet
+pub fn configured_name() -> String {
+    env::var(PROVIDER_ENV_VAR)
+        .ok()
+        .or_else(|| repo::config().ok()?.get_string(PROVIDER_CONFIG_KEY).ok())
+        .unwrap_or_else(|| "skynet".to_string())
+}
+
+/// instantiate the configured provider
+pub fn from_config() -> Result<Box<dyn StorageProvider + Send>> {
+    let name = configured_name();
+
+    log::debug!("using provider: {}", &name);
+
+    from_name(&name)
+}
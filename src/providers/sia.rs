@@ -0,0 +1,451 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use git_lfs_spec::transfer::custom::{Download, Upload};
+use git_lfs_spec::Object;
+use isahc::*;
+use tokio::io::AsyncWrite;
+
+use crate::credentials::{self, CredentialSpec};
+use crate::http::HttpSettings;
+use crate::pointer;
+use crate::provider::{Capabilities, ObjectReader, RemoteObject, StorageProvider};
+use crate::throttle::{self, Limits};
+use crate::tmp::PartialDownload;
+
+const DEFAULT_RENTERD_URL: &str = "http://localhost:9980";
+
+const DEFAULT_BUCKET: &str = "default";
+
+/// directory in the bucket the objects are stored in
+const OBJECT_DIR: &str = "git-lfs/";
+
+/// how many entries are listed per request
+const LIST_PAGE_SIZE: usize = 1000;
+
+/// settings read by the Sia provider, see `credentials::resolve`
+pub const SIA_CREDENTIALS: CredentialSpec = CredentialSpec {
+    provider: "sia",
+    vars: &["SIA_RENTERD_URL", "SIA_RENTERD_PASSWORD", "SIA_BUCKET"],
+    url_var: "SIA_RENTERD_URL",
+    default_url: DEFAULT_RENTERD_URL,
+    username_var: None,
+    password_var: "SIA_RENTERD_PASSWORD",
+};
+
+/// stores objects on a Sia renter through the renterd worker and bus APIs
+pub struct SiaProvider {
+    url: String,
+    password: Option<String>,
+    bucket: String,
+    limits: Limits,
+    http: HttpSettings,
+}
+
+impl SiaProvider {
+    pub fn new_from_env() -> Result<Self> {
+        let mut env_variables = credentials::resolve(&SIA_CREDENTIALS)?;
+
+        let url = env_variables.remove("SIA_RENTERD_URL")
+            .unwrap_or(DEFAULT_RENTERD_URL.to_string());
+        let bucket = env_variables.remove("SIA_BUCKET")
+            .unwrap_or(DEFAULT_BUCKET.to_string());
+
+        log::debug!("using renterd at {}, bucket {}", &url, &bucket);
+
+        Ok(Self::new(&url, env_variables.remove("SIA_RENTERD_PASSWORD"), &bucket))
+    }
+
+    pub fn new(url: &str, password: Option<String>, bucket: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            password,
+            bucket: bucket.to_string(),
+            limits: Limits::from_config("sia"),
+            http: HttpSettings::from_config(),
+        }
+    }
+
+    /// a request to the `worker` or `bus` objects API, authenticated with the API password
+    fn request(&self, method: &str, api: &str, path: &str) -> isahc::http::request::Builder {
+        self.request_with_query(method, api, path, "")
+    }
+
+    fn request_with_query(&self, method: &str, api: &str, path: &str, query: &str) -> isahc::http::request::Builder {
+        let request = self.http.configure(
+            isahc::Request::builder()
+                .method(method)
+                .uri(format!("{}/api/{}/objects/{}?bucket={}{}", &self.url, api, path, &self.bucket, query)),
+        );
+
+        match &self.password {
+            Some(password) => request.header(
+                "Authorization",
+                format!("Basic {}", base64::encode(format!(":{}", password))),
+            ),
+            None => request,
+        }
+    }
+
+    fn object_path(oid: &str) -> String {
+        format!("{}{}", OBJECT_DIR, oid)
+    }
+
+    /// the size renterd has stored for an object, `None` if it doesn't have it
+    async fn stored_size(&self, oid: &str) -> Result<Option<u64>> {
+        let _permit = self.limits.request_permit().await;
+
+        let mut response = self
+            .request("GET", "bus", &Self::object_path(oid))
+            .body(())?
+            .send_async()
+            .await?;
+
+        if response.status() == 404 {
+            return Ok(None);
+        }
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("There was an error trying to look up {} on renterd: {}", oid, response.status()));
+        }
+
+        let metadata: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+
+        // older renterd versions wrap the metadata in an `object` field
+        Ok(metadata
+            .get("object")
+            .unwrap_or(&metadata)
+            .get("size")
+            .and_then(|size| size.as_u64()))
+    }
+}
+
+#[async_trait]
+impl StorageProvider for SiaProvider {
+    fn name(&self) -> &'static str {
+        "sia"
+    }
+
+    fn target(&self) -> String {
+        format!("sia:{}/{}", &self.url, &self.bucket)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            needs_mapping: false,
+            supports_range: true,
+            supports_delete: true,
+            supports_list: true,
+            // a renterd bucket is expected to hold a single repository
+            scoped_list: true,
+            max_object_size: None,
+            recommended_concurrency: 4,
+        }
+    }
+
+    async fn download(&self, download: &Download) -> Result<String> {
+        let _permit = self.limits.request_permit().await;
+        let partial = PartialDownload::new(&download.object)?;
+
+        partial
+            .fetch(
+                self.request("GET", "worker", &Self::object_path(&download.object.oid)),
+                self.limits.download.clone(),
+                self.capabilities().supports_range,
+            )
+            .await?;
+
+        Ok(partial.complete().await?.to_string_lossy().to_string())
+    }
+
+    async fn download_writer(&self, object: &Object, writer: &mut (dyn AsyncWrite + Send + Unpin)) -> Result<()> {
+        let _permit = self.limits.request_permit().await;
+
+        let mut response = self
+            .request("GET", "worker", &Self::object_path(&object.oid))
+            .body(())?
+            .send_async()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("There was an error trying to download from renterd: {}", response.status()));
+        }
+
+        throttle::copy_to(response.body_mut(), writer, self.limits.download.clone()).await?;
+
+        Ok(())
+    }
+
+    async fn upload_reader(&self, object: &Object, reader: ObjectReader) -> Result<()> {
+        log::debug!("uploading {}...", &object.oid);
+
+        let _permit = self.limits.request_permit().await;
+
+        let mut response = self
+            .request("PUT", "worker", &Self::object_path(&object.oid))
+            .header("Content-Type", "application/octet-stream")
+            .body(throttle::body_from(reader, object.size, self.limits.upload.clone()))?
+            .send_async()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "There was an error trying to upload to renterd: {} {}", response.status(), response.text().await?
+            ));
+        }
+
+        log::debug!("upload complete: {}", &object.oid);
+
+        Ok(())
+    }
+
+    async fn is_uploaded(&self, upload: &Upload) -> Result<bool> {
+        Ok(self.stored_size(&upload.object.oid).await? == Some(upload.object.size))
+    }
+
+    async fn list(&self) -> Result<Vec<RemoteObject>> {
+        let _permit = self.limits.request_permit().await;
+
+        let mut objects = vec![];
+        let mut offset = 0;
+
+        loop {
+            let mut response = self
+                .request_with_query("GET", "bus", OBJECT_DIR, &format!("&offset={}&limit={}", offset, LIST_PAGE_SIZE))
+                .body(())?
+                .send_async()
+                .await?;
+
+            if response.status() == 404 {
+                break;
+            }
+
+            if !response.status().is_success() {
+                return Err(anyhow::anyhow!("There was an error trying to list objects on renterd: {}", response.status()));
+            }
+
+            let listing: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+            let entries = listing.get("entries").and_then(|entries| entries.as_array()).cloned().unwrap_or_default();
+
+            objects.extend(entries.iter().filter_map(|entry| {
+                let name = entry.get("name")?.as_str()?;
+                let oid = name.rsplit('/').next()?;
+
+                if !pointer::is_oid(oid) {
+                    return None;
+                }
+
+                Some(RemoteObject {
+                    oid: oid.to_string(),
+                    size: entry.get("size").and_then(|size| size.as_u64()),
+                    uploaded_at: entry
+                        .get("modTime")
+                        .and_then(|time| time.as_str())
+                        .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                        .map(|time| time.with_timezone(&Utc)),
+                    address: Some(name.to_string()),
+                })
+            }));
+
+            if listing.get("hasMore").and_then(|more| more.as_bool()) != Some(true) || entries.is_empty() {
+                break;
+            }
+
+            offset += entries.len();
+        }
+
+        Ok(objects)
+    }
+
+    async fn delete(&self, oid: &str) -> Result<()> {
+        let _permit = self.limits.request_permit().await;
+
+        let response = self
+            .request("DELETE", "worker", &Self::object_path(oid))
+            .body(())?
+            .send_async()
+            .await?;
+
+        // already gone is as good as deleted
+        if !response.status().is_success() && response.status() != 404 {
+            return Err(anyhow::anyhow!("There was an error trying to delete {} from renterd: {}", oid, response.status()));
+        }
+
+        log::debug!("deleted {}", oid);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+    use git_lfs_spec::Object;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Method, Request, Response, Server, StatusCode};
+    use pretty_assertions::assert_eq;
+    use crate::oid::oid_of_bytes;
+
+    const FILE: &[u8] = b"hello world";
+    const PASSWORD: &str = "test-password";
+
+    type Objects = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    fn status(status: StatusCode) -> Response<Body> {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = status;
+        response
+    }
+
+    fn json(value: serde_json::Value) -> Response<Body> {
+        Response::new(Body::from(value.to_string()))
+    }
+
+    /// the part of the renterd API the provider uses, keeping the objects in memory
+    async fn renterd(objects: Objects, request: Request<Body>) -> std::result::Result<Response<Body>, Infallible> {
+        let authorization = format!("Basic {}", base64::encode(format!(":{}", PASSWORD)));
+
+        if request.headers().get("Authorization").and_then(|value| value.to_str().ok()) != Some(authorization.as_str()) {
+            return Ok(status(StatusCode::UNAUTHORIZED));
+        }
+
+        let path = request.uri().path().to_string();
+
+        if let Some(name) = path.strip_prefix("/api/worker/objects/") {
+            let name = name.to_string();
+
+            return Ok(match request.method().clone() {
+                Method::PUT => {
+                    let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                    objects.lock().unwrap().insert(name, body.to_vec());
+                    status(StatusCode::OK)
+                }
+                Method::GET => match objects.lock().unwrap().get(&name) {
+                    Some(data) => Response::new(Body::from(data.clone())),
+                    None => status(StatusCode::NOT_FOUND),
+                },
+                Method::DELETE => match objects.lock().unwrap().remove(&name) {
+                    Some(_) => status(StatusCode::OK),
+                    None => status(StatusCode::NOT_FOUND),
+                },
+                _ => status(StatusCode::METHOD_NOT_ALLOWED),
+            });
+        }
+
+        if let Some(name) = path.strip_prefix("/api/bus/objects/") {
+            let objects = objects.lock().unwrap();
+
+            if name.ends_with('/') {
+                let entries: Vec<_> = objects
+                    .iter()
+                    .filter(|(key, _)| key.starts_with(name))
+                    .map(|(key, data)| serde_json::json!({
+                        "name": format!("/{}", key),
+                        "size": data.len(),
+                        "modTime": "2024-01-01T00:00:00Z",
+                    }))
+                    .collect();
+
+                return Ok(json(serde_json::json!({ "entries": entries, "hasMore": false })));
+            }
+
+            return Ok(match objects.get(name) {
+                Some(data) => json(serde_json::json!({ "object": { "name": format!("/{}", name), "size": data.len() } })),
+                None => status(StatusCode::NOT_FOUND),
+            });
+        }
+
+        Ok(status(StatusCode::NOT_FOUND))
+    }
+
+    /// start the stand-in on a free port, returning its URL and the objects it holds
+    fn start_renterd() -> (String, Objects) {
+        let objects = Objects::default();
+        let served = objects.clone();
+
+        let make_service = make_service_fn(move |_connection| {
+            let objects = served.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |request| renterd(objects.clone(), request))) }
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        (url, objects)
+    }
+
+    #[tokio::test]
+    async fn upload_list_download_and_delete_round_trip() {
+        let (url, objects) = start_renterd();
+        let client = SiaProvider::new(&url, Some(PASSWORD.to_string()), DEFAULT_BUCKET);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        std::fs::write(&path, FILE).unwrap();
+
+        let oid = oid_of_bytes(FILE);
+        let upload = Upload {
+            object: Object {
+                oid: oid.clone(),
+                size: FILE.len() as u64,
+            },
+            path,
+        };
+
+        assert!(!client.is_uploaded(&upload).await.unwrap());
+
+        client.upload(&upload).await.unwrap();
+
+        assert!(client.is_uploaded(&upload).await.unwrap());
+        assert_eq!(objects.lock().unwrap().get(&format!("git-lfs/{}", &oid)), Some(&FILE.to_vec()));
+
+        let listed = client.list().await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].oid, oid);
+        assert_eq!(listed[0].size, Some(FILE.len() as u64));
+
+        let downloaded = client.download(&Download { object: upload.object.clone() }).await.unwrap();
+        assert_eq!(std::fs::read(&downloaded).unwrap(), FILE);
+        std::fs::remove_file(downloaded).unwrap();
+
+        client.delete(&oid).await.unwrap();
+        assert!(!client.is_uploaded(&upload).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn objects_stream_without_files() {
+        let (url, _objects) = start_renterd();
+        let client = SiaProvider::new(&url, Some(PASSWORD.to_string()), DEFAULT_BUCKET);
+
+        let object = Object {
+            oid: oid_of_bytes(FILE),
+            size: FILE.len() as u64,
+        };
+
+        client.upload_reader(&object, Box::new(std::io::Cursor::new(FILE.to_vec()))).await.unwrap();
+
+        let mut downloaded = vec![];
+        client.download_writer(&object, &mut downloaded).await.unwrap();
+        assert_eq!(downloaded, FILE);
+    }
+
+    #[tokio::test]
+    async fn wrong_password_is_an_error() {
+        let (url, _objects) = start_renterd();
+        let client = SiaProvider::new(&url, Some("wrong".to_string()), DEFAULT_BUCKET);
+
+        let upload = Upload {
+            object: Object {
+                oid: oid_of_bytes(FILE),
+                size: FILE.len() as u64,
+            },
+            path: Default::default(),
+        };
+
+        assert!(client.is_uploaded(&upload).await.is_err());
+    }
+}
@@ -1,13 +1,23 @@
-use std::{env, io::Write, path::Path};
-use tokio::io::{AsyncBufRead, AsyncBufReadExt};
-use skynet_rs::{SkynetClient, UploadOptions, DownloadOptions, MetadataOptions, SkynetClientOptions, SkynetError};
+use std::{collections::{BTreeMap, HashMap, HashSet}, env, path::Path};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use skynet_rs::{SkynetClient, UploadOptions, SkynetClientOptions, SkynetError};
 use isahc::*;
 use anyhow::{Context, Result};
 use git_lfs_spec::transfer::custom::{Download, Upload};
 use async_trait::async_trait;
 use git_lfs_spec::Object;
 use git_lfs_spec::transfer::custom;
-use crate::provider::StorageProvider;
+use crate::credentials::{self, CredentialSpec};
+use crate::http::HttpSettings;
+use crate::pointer;
+use crate::provider::{self, Capabilities, ObjectReader, RemoteObject, StorageProvider};
+use crate::throttle::{self, Limits, Throttled};
+use crate::tmp::PartialDownload;
+use super::skynet_registry::{self, MappingIndex, RegistryKeypair};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 
 #[derive(Copy, Clone, Debug)]
 pub enum UploadStrategy {
@@ -15,20 +25,80 @@ pub enum UploadStrategy {
     CURL
 }
 
+const DEFAULT_PORTAL_URL: &str = "https://skynetfree.net";
+
+/// settings read by the Skynet provider, see `credentials::resolve`
+pub const SKYNET_CREDENTIALS: CredentialSpec = CredentialSpec {
+    provider: "skynet",
+    vars: &["SKYNET_PORTAL_URL", "SKYNET_API_KEY"],
+    url_var: "SKYNET_PORTAL_URL",
+    default_url: DEFAULT_PORTAL_URL,
+    username_var: None,
+    password_var: "SKYNET_API_KEY",
+};
+
+/// git config key prefix of the OID => Skylink mappings
+const MAPPING_KEY_PREFIX: &str = "lfs.customtransfer.skynet.mapping.oid-";
+
+/// git config key prefix of the time (unix seconds) a skylink was uploaded or last pinned
+const UPLOADED_KEY_PREFIX: &str = "lfs.customtransfer.skynet.uploaded.oid-";
+
+/// git config key prefix of the portal a skylink was uploaded to
+const PORTAL_KEY_PREFIX: &str = "lfs.customtransfer.skynet.portal.oid-";
+
+/// days a portal keeps content pinned, `lfs-web3.skynet.retentiondays`
+const DEFAULT_RETENTION_DAYS: i64 = 90;
+
+/// days before expiry a skylink is due for renewal, `lfs-web3.skynet.renewdays`
+const DEFAULT_RENEW_DAYS: i64 = 14;
+
+/// version of the published mapping index format
+const INDEX_VERSION: u32 = 1;
+
+/// how many metadata requests are in flight at the same time when checking many skylinks
+const METADATA_CHECK_JOBS: usize = 32;
+
+/// an OID => Skylink mapping together with when and where it was uploaded
+#[derive(Clone, Debug)]
+pub struct Mapping {
+    pub oid: String,
+    pub skylink: String,
+    pub uploaded_at: Option<DateTime<Utc>>,
+    pub portal: Option<String>,
+}
+
+/// what renewing a skylink came down to
+#[derive(Clone, Debug, PartialEq)]
+pub enum Renewal {
+    /// the portal pinned the existing skylink again
+    Pinned,
+    /// the content was gone and got uploaded from the local copy
+    Reuploaded,
+}
+
 pub struct SkynetProvider {
     client: SkynetClient,
-    pub strategy: UploadStrategy
+    pub strategy: UploadStrategy,
+    limits: Limits,
+    http: HttpSettings,
+    /// the published mapping index, loaded on the first oid without a mapping in git config
+    index: tokio::sync::OnceCell<BTreeMap<String, String>>,
+    /// whether mappings were added or removed since the index was last published
+    index_changed: AtomicBool,
+    /// oids whose mappings were removed, so publishing doesn't bring them back
+    removed: Mutex<HashSet<String>>,
+    /// days the portal keeps content pinned
+    retention_days: i64,
+    /// days before expiry a skylink is due for renewal
+    renew_days: i64,
 }
 
 impl SkynetProvider {
     pub fn new_from_env(strategy: UploadStrategy) -> Result<Self> {
-        let mut env_variables
-            = env_file_reader::read_file(".skynet.env")?;
-
-        log::debug!("skynet vars: {:#?}", &env_variables);
+        let mut env_variables = credentials::resolve(&SKYNET_CREDENTIALS)?;
 
         let portal_url = env_variables.remove("SKYNET_PORTAL_URL")
-            .unwrap_or("https://skynetfree.net".to_string());
+            .unwrap_or(DEFAULT_PORTAL_URL.to_string());
 
         log::debug!("using Skynet portal: {}", &portal_url);
 
@@ -39,66 +109,446 @@ impl SkynetProvider {
 
         Ok(Self {
             client,
-            strategy
+            strategy,
+            limits: Limits::from_config("skynet"),
+            http: HttpSettings::from_config(),
+            index: tokio::sync::OnceCell::new(),
+            index_changed: AtomicBool::new(false),
+            removed: Mutex::new(HashSet::new()),
+            retention_days: Self::config_days("lfs-web3.skynet.retentiondays", DEFAULT_RETENTION_DAYS)?,
+            renew_days: Self::config_days("lfs-web3.skynet.renewdays", DEFAULT_RENEW_DAYS)?,
         })
     }
 
+    fn config_days(key: &str, default: i64) -> Result<i64> {
+        Ok(Self::git_config()?.get_i64(key).unwrap_or(default))
+    }
+
     fn git_map_key(oid: &String) -> String {
-        format!("lfs.customtransfer.skynet.mapping.oid-{}", oid)
+        format!("{}{}", MAPPING_KEY_PREFIX, oid)
+    }
+
+    /// every oid that has an OID => Skylink mapping in git config
+    pub fn mapped_oids() -> Result<Vec<String>> {
+        let gitconf = Self::git_config()?;
+        let mut oids = vec![];
+
+        for entry in &gitconf.entries(Some(r"^lfs\.customtransfer\.skynet\.mapping\.oid-"))? {
+            if let Some(oid) = entry?.name().and_then(|name| name.strip_prefix(MAPPING_KEY_PREFIX)) {
+                oids.push(oid.to_string());
+            }
+        }
+
+        Ok(oids)
     }
 
-    fn git_save_mapping(oid: &String, skylink: &String) {
-        let mut gitconf = Self::git_config();
+    fn git_save_mapping(&self, oid: &String, skylink: &String) -> Result<()> {
+        let mut gitconf = Self::git_config()?;
         gitconf.set_str(
             Self::git_map_key(oid).as_str(),
             base64::encode(skylink.replace("sia://", "")).as_str()
-        ).expect("failed to write OID => Skylink mapping");
+        ).context("failed to write OID => Skylink mapping")?;
+
+        self.git_touch_mapping(oid)?;
+        self.index_changed.store(true, Ordering::SeqCst);
+
+        Ok(())
     }
 
-    fn get_skylink(oid: &String) -> Option<String> {
-        Self::git_config()
-            .get_string(Self::git_map_key(oid).as_str())
-            .ok()
-            .map(|skylinkb64| format!("{:?}", base64::decode(skylinkb64).unwrap()))
+    /// record that the skylink for an oid was (re-)pinned on our portal just now
+    fn git_touch_mapping(&self, oid: &String) -> Result<()> {
+        let mut gitconf = Self::git_config()?;
+        gitconf.set_i64(
+            format!("{}{}", UPLOADED_KEY_PREFIX, oid).as_str(),
+            Utc::now().timestamp()
+        ).context("failed to write skylink upload time")?;
+        gitconf.set_str(
+            format!("{}{}", PORTAL_KEY_PREFIX, oid).as_str(),
+            self.client.get_portal_url().to_string().as_str()
+        ).context("failed to write skylink portal")?;
+
+        Ok(())
     }
 
-    // todo: return response
-    async fn upload_isahc(&self, upload: &Upload) -> Result<()> {
-        let file = std::fs::File::open(upload.path.clone())?;
+    fn git_remove_mapping(oid: &String) -> Result<()> {
+        let mut gitconf = Self::git_config()?;
 
-        log::debug!("instantiated file object");
+        // nothing to do when there was no mapping
+        let _ = gitconf.remove(Self::git_map_key(oid).as_str());
+        let _ = gitconf.remove(format!("{}{}", UPLOADED_KEY_PREFIX, oid).as_str());
+        let _ = gitconf.remove(format!("{}{}", PORTAL_KEY_PREFIX, oid).as_str());
 
-        let endpoint = format!("{}/skynet/skyfile/{}",
-                               self.client.get_portal_url(),
-                               &upload.object.oid);
+        Ok(())
+    }
 
-        // Perform the upload.
-        let mut response = isahc::Request::post(endpoint)
-            // todo: we have hardcoded the api key expectation here
-            .header("Skynet-Api-Key", self.client.get_options().api_key.clone().unwrap())
-            .header("Content-Type", "multipart/form-data")
-            // todo: set file to the 'file' form field
-            .body(file)?
-            .send();
+    /// the mapping for an oid, including when and where it was uploaded if that was recorded
+    pub fn get_mapping(oid: &String) -> Result<Option<Mapping>> {
+        let skylink = match Self::get_skylink(oid)? {
+            Some(skylink) => skylink,
+            None => return Ok(None),
+        };
+        let gitconf = Self::git_config()?;
+
+        Ok(Some(Mapping {
+            oid: oid.clone(),
+            skylink,
+            uploaded_at: gitconf
+                .get_i64(format!("{}{}", UPLOADED_KEY_PREFIX, oid).as_str())
+                .ok()
+                .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single()),
+            portal: gitconf
+                .get_string(format!("{}{}", PORTAL_KEY_PREFIX, oid).as_str())
+                .ok(),
+        }))
+    }
 
-        log::debug!("passed http call");
+    /// every mapping in git config
+    pub fn mappings() -> Result<Vec<Mapping>> {
+        Self::mapped_oids()?
+            .iter()
+            .filter_map(|oid| Self::get_mapping(oid).transpose())
+            .collect()
+    }
 
-        if response.is_err() {
-            return Err(anyhow::anyhow!("There was an error trying to upload to skynet portal: {:?}", response))
+    /// when the portal is expected to unpin the skylink, if the upload time is known
+    pub fn expires_at(&self, mapping: &Mapping) -> Option<DateTime<Utc>> {
+        mapping.uploaded_at.map(|uploaded_at| uploaded_at + Duration::days(self.retention_days))
+    }
+
+    /// whether the skylink expires within the renewal window, or may already have expired unnoticed
+    pub fn is_due_for_renewal(&self, mapping: &Mapping, renew_days: Option<i64>) -> bool {
+        let renew_days = renew_days.unwrap_or(self.renew_days);
+
+        match self.expires_at(mapping) {
+            Some(expires_at) => expires_at - Duration::days(renew_days) <= Utc::now(),
+            // mappings from before upload times were recorded could be close to expiring
+            None => true,
         }
+    }
 
-        else if let Ok(mut resp) = response {
-            let skylink = resp.text()?;
+    /// a request to the portal, authenticated when an API key is configured
+    fn portal_request(&self, method: &str, path: &str) -> isahc::http::request::Builder {
+        let request = self.http.configure(
+            isahc::Request::builder()
+                .method(method)
+                .uri(format!("{}/{}", self.client.get_portal_url(), path)),
+        );
+
+        match self.client.get_options().api_key.clone() {
+            Some(api_key) => request.header("Skynet-Api-Key", api_key),
+            None => request,
+        }
+    }
 
-            log::debug!("upload complete: {}", &skylink);
+    /// The length of a skyfile and the oid it was uploaded for, as `provider::matches_stored` expects them.
+    ///
+    /// Objects are uploaded as skyfiles named after their oid. Returns `None` when the portal doesn't have the skylink.
+    async fn skyfile_metadata(&self, skylink: &str) -> Result<Option<(Option<u64>, HashMap<String, String>)>> {
+        let _permit = self.limits.request_permit().await;
 
-            // save mapping
-            Self::git_save_mapping(&upload.object.oid, &skylink);
+        let mut response = self
+            .portal_request("GET", &format!("skynet/metadata/{}", skylink))
+            .body(())?
+            .send_async()
+            .await?;
+
+        if response.status() == 404 {
+            return Ok(None);
+        }
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("failed to get metadata of {}: {}", skylink, response.status()));
+        }
+
+        let metadata: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+        let mut recorded = HashMap::new();
+
+        // skyfiles uploaded from elsewhere have arbitrary names
+        if let Some(filename) = metadata.get("filename").and_then(|filename| filename.as_str()) {
+            if pointer::is_oid(filename) {
+                recorded.insert(provider::OID_METADATA.to_string(), filename.to_string());
+            }
+        }
+
+        Ok(Some((metadata.get("length").and_then(|length| length.as_u64()), recorded)))
+    }
+
+    /// ask the portal to pin a skylink, which restarts its retention period
+    pub async fn pin(&self, skylink: &str) -> Result<()> {
+        let _permit = self.limits.request_permit().await;
+
+        let response = self
+            .portal_request("POST", &format!("skynet/pin/{}", skylink))
+            .body(())?
+            .send_async()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("failed to pin {}: {}", skylink, response.status()));
         }
 
         Ok(())
     }
 
+    /// Re-pin a skylink, or upload the local copy again if the portal can't pin it anymore.
+    pub async fn renew(&self, mapping: &Mapping, local_path: &Path) -> Result<Renewal> {
+        match self.pin(&mapping.skylink).await {
+            Ok(()) => {
+                self.git_touch_mapping(&mapping.oid)?;
+                Ok(Renewal::Pinned)
+            }
+            Err(err) if local_path.is_file() => {
+                log::warn!("{:#}, uploading {} again", err, &mapping.oid);
+
+                let upload = Upload {
+                    object: Object {
+                        oid: mapping.oid.clone(),
+                        size: std::fs::metadata(local_path)?.len(),
+                    },
+                    path: local_path.to_path_buf(),
+                };
+
+                self.upload(&upload).await?;
+                Ok(Renewal::Reuploaded)
+            }
+            Err(err) => Err(err.context("no local copy to upload again")),
+        }
+    }
+
+    /// the keypair the mapping index is published under, if this repository publishes one
+    fn registry_keypair() -> Result<Option<RegistryKeypair>> {
+        match Self::git_config()?.get_string(skynet_registry::REGISTRY_SEED_KEY) {
+            Ok(seed) => {
+                crate::logging::register_secret(&seed);
+                Ok(Some(RegistryKeypair::from_seed(&seed)?))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn registry_data_key() -> Result<String> {
+        Ok(Self::git_config()?
+            .get_string(skynet_registry::REGISTRY_DATA_KEY_KEY)
+            .unwrap_or_else(|_| skynet_registry::DEFAULT_DATA_KEY.to_string()))
+    }
+
+    /// the public key to resolve the mapping index with, if one is configured
+    fn registry_public_key() -> Result<Option<String>> {
+        match Self::git_config()?.get_string(skynet_registry::REGISTRY_PUBLIC_KEY_KEY) {
+            Ok(public_key) => Ok(Some(public_key)),
+            Err(_) => Ok(Self::registry_keypair()?.map(|keypair| keypair.public_key())),
+        }
+    }
+
+    /// the verified registry entry of the mapping index, `None` if it was never published
+    async fn registry_entry(&self, public_key: &str, data_key: &str) -> Result<Option<skynet_registry::Entry>> {
+        let _permit = self.limits.request_permit().await;
+
+        let mut response = self
+            .portal_request("GET", &format!("skynet/registry?{}", skynet_registry::lookup_query(public_key, data_key)))
+            .body(())?
+            .send_async()
+            .await?;
+
+        if response.status() == 404 {
+            return Ok(None);
+        }
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("failed to look up the registry entry: {}", response.status()));
+        }
+
+        let entry = skynet_registry::parse_entry(&serde_json::from_str(&response.text().await?)?)?;
+        skynet_registry::verify_entry(public_key, data_key, &entry)?;
+
+        Ok(Some(entry))
+    }
+
+    async fn fetch_index(&self, skylink: &str) -> Result<MappingIndex> {
+        let _permit = self.limits.request_permit().await;
+
+        let mut response = self.portal_request("GET", skylink).body(())?.send_async().await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("failed to download the mapping index {}: {}", skylink, response.status()));
+        }
+
+        Ok(serde_json::from_str(&response.text().await?)?)
+    }
+
+    /// the mapping index published for the repository, empty when no public key is configured
+    async fn published_index(&self) -> Result<MappingIndex> {
+        let public_key = match Self::registry_public_key()? {
+            Some(public_key) => public_key,
+            None => return Ok(MappingIndex::default()),
+        };
+
+        match self.registry_entry(&public_key, &Self::registry_data_key()?).await? {
+            Some(entry) => self.fetch_index(&skynet_registry::skylink_from_bytes(&entry.data)).await,
+            None => Ok(MappingIndex::default()),
+        }
+    }
+
+    /// the skylink of an oid from git config, or else from the published mapping index
+    async fn resolve_skylink(&self, oid: &String) -> Result<Option<String>> {
+        if let Some(skylink) = Self::get_skylink(oid)? {
+            return Ok(Some(skylink));
+        }
+
+        let index = self
+            .index
+            .get_or_try_init(|| async { self.published_index().await.map(|index| index.objects) })
+            .await;
+
+        match index {
+            Ok(objects) => Ok(objects.get(oid).cloned()),
+            Err(err) => {
+                log::warn!("could not read the published mapping index: {:#}", err);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Publish the mappings as a skyfile and point the repository's registry entry at it.
+    ///
+    /// Mappings published from other clones are kept, unless they were removed here.
+    pub async fn publish_index(&self, keypair: &RegistryKeypair) -> Result<String> {
+        let data_key = Self::registry_data_key()?;
+        let current = self.registry_entry(&keypair.public_key(), &data_key).await?;
+
+        let mut index = match &current {
+            Some(entry) => self.fetch_index(&skynet_registry::skylink_from_bytes(&entry.data)).await?,
+            None => MappingIndex::default(),
+        };
+        index.version = INDEX_VERSION;
+
+        for oid in self.removed.lock().unwrap().iter() {
+            index.objects.remove(oid);
+        }
+        for mapping in Self::mappings()? {
+            index.objects.insert(mapping.oid, mapping.skylink);
+        }
+
+        let json = serde_json::to_vec(&index)?;
+        let size = json.len() as u64;
+
+        let skylink = self.upload_skyfile(Box::new(std::io::Cursor::new(json)), size, "git-lfs-web3-index.json").await?;
+        let revision = current.map(|entry| entry.revision + 1).unwrap_or(0);
+        let request = keypair.entry_request(&data_key, &skynet_registry::skylink_to_bytes(&skylink)?, revision);
+
+        let response = self
+            .portal_request("POST", "skynet/registry")
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_vec(&request)?)?
+            .send_async()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("failed to update the registry entry: {}", response.status()));
+        }
+
+        self.index_changed.store(false, Ordering::SeqCst);
+        log::info!("published mapping index {} with {} objects at revision {}", &skylink, index.objects.len(), revision);
+
+        Ok(skylink)
+    }
+
+    /// Create the keypair the mapping index is published under if there is none yet, and publish the index.
+    ///
+    /// Returns the public key downloaders need to resolve the index.
+    pub async fn init_registry(&self) -> Result<String> {
+        let keypair = match Self::registry_keypair()? {
+            Some(keypair) => keypair,
+            None => {
+                let keypair = RegistryKeypair::generate();
+                crate::logging::register_secret(&keypair.seed());
+
+                let mut local = Self::git_config()?.open_level(git2::ConfigLevel::Local)?;
+                local.set_str(skynet_registry::REGISTRY_SEED_KEY, &keypair.seed())?;
+                local.set_str(skynet_registry::REGISTRY_PUBLIC_KEY_KEY, &keypair.public_key())?;
+
+                keypair
+            }
+        };
+
+        self.publish_index(&keypair).await?;
+
+        Ok(keypair.public_key())
+    }
+
+    fn get_skylink(oid: &String) -> Result<Option<String>> {
+        Ok(Self::git_config()?
+            .get_string(Self::git_map_key(oid).as_str())
+            .ok()
+            .and_then(|skylinkb64| base64::decode(skylinkb64).ok())
+            .and_then(|skylink| String::from_utf8(skylink).ok()))
+    }
+
+    /// upload as a multipart form streamed from the file, so the upload limit applies
+    async fn upload_isahc(&self, upload: &Upload) -> Result<()> {
+        let file = tokio::fs::File::open(&upload.path)
+            .await
+            .with_context(|| format!("could not open {}", upload.path.display()))?;
+
+        self.upload_object(&upload.object, Box::new(file)).await
+    }
+
+    /// upload an object's content as a skyfile and map its oid to the skylink
+    async fn upload_object(&self, object: &Object, reader: ObjectReader) -> Result<()> {
+        let skylink = self.upload_skyfile(reader, object.size, &object.oid).await?;
+
+        log::debug!("upload complete: {}", &skylink);
+
+        // save mapping
+        self.git_save_mapping(&object.oid, &skylink)
+    }
+
+    /// upload `size` bytes from `reader` as a skyfile named `filename`, returning its skylink
+    async fn upload_skyfile(&self, reader: ObjectReader, size: u64, filename: &str) -> Result<String> {
+        let boundary = format!("git-lfs-web3-{}", filename);
+        let head = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+            &boundary, filename
+        );
+        let tail = format!("\r\n--{}--\r\n", &boundary);
+        let length = head.len() as u64 + size + tail.len() as u64;
+
+        let content = Throttled::new(reader, self.limits.upload.clone()).compat();
+        let body = futures::io::AsyncReadExt::chain(
+            futures::io::AsyncReadExt::chain(futures::io::Cursor::new(head.into_bytes()), content),
+            futures::io::Cursor::new(tail.into_bytes()),
+        );
+
+        let mut response = self
+            .portal_request("POST", "skynet/skyfile")
+            .header("Content-Type", format!("multipart/form-data; boundary={}", &boundary))
+            .body(AsyncBody::from_reader_sized(body, length))?
+            .send_async()
+            .await?;
+
+        let status = response.status();
+        let text = response.text().await?;
+
+        if !status.is_success() {
+            return Err(anyhow::anyhow!("There was an error trying to upload to skynet portal: {} {}", status, text));
+        }
+
+        serde_json::from_str::<serde_json::Value>(&text)?
+            .get("skylink")
+            .and_then(|skylink| skylink.as_str())
+            .map(|skylink| skylink.to_string())
+            .ok_or_else(|| anyhow::anyhow!("no skylink in portal response: {}", text))
+    }
+
+    /// stream a skylink's content into a partial download, so the download limit applies and
+    /// interrupted downloads are resumed
+    async fn download_isahc(&self, skylink: &str, partial: &PartialDownload) -> Result<()> {
+        partial
+            .fetch(self.portal_request("GET", skylink), self.limits.download.clone(), self.capabilities().supports_range)
+            .await
+            .with_context(|| format!("failed to download {}", skylink))
+    }
+
     async fn upload_skynet_rs(&self, upload: &Upload) -> Result<()> {
         // upload file and get skylink
         let uploadres = self.client.upload_file(
@@ -113,9 +563,7 @@ impl SkynetProvider {
             log::debug!("upload complete: {}", &skylink);
 
             // save mapping
-            Self::git_save_mapping(&upload.object.oid, skylink);
-
-            return Ok(())
+            return self.git_save_mapping(&upload.object.oid, skylink)
         }
 
         // error
@@ -127,26 +575,76 @@ impl SkynetProvider {
 
 #[async_trait]
 impl StorageProvider for SkynetProvider {
+    fn name(&self) -> &'static str {
+        "skynet"
+    }
+
+    fn target(&self) -> String {
+        format!("skynet:{}", self.client.get_portal_url())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            needs_mapping: true,
+            supports_range: true,
+            // unpinning from our portal, other portals may still serve the content
+            supports_delete: true,
+            // only the mappings of this repository
+            supports_list: true,
+            scoped_list: true,
+            max_object_size: None,
+            recommended_concurrency: 4,
+        }
+    }
+
+    fn address(&self, oid: &str) -> Option<String> {
+        Self::get_skylink(&oid.to_string()).unwrap_or_else(|err| {
+            log::warn!("could not look up the skylink of {}: {:#}", oid, err);
+            None
+        })
+    }
+
     async fn download(&self, download: &Download) -> Result<String> {
-        match Self::get_skylink(&download.object.oid) {
+        match self.resolve_skylink(&download.object.oid).await? {
             Some(skylink) => {
-                let output_path = format!("/tmp/{}", &download.object.oid);
-
-                self
-                    .client
-                    .download_file(&output_path, &skylink, DownloadOptions::default())
-                    .await
-                    .map(|_| output_path)
-                    .map_err(|skynet_err| anyhow::anyhow!("an error occurred in skynet-rs: {:#?}", skynet_err))
+                let _permit = self.limits.request_permit().await;
+                let partial = PartialDownload::new(&download.object)?;
+
+                self.download_isahc(&skylink, &partial).await?;
+
+                Ok(partial.complete().await?.to_string_lossy().to_string())
             }
 
             // no skylink found in mapping
             None => {
-                Err(anyhow::anyhow!("no skylink found in .git/config mapping for {}", &download.object.oid))
+                Err(anyhow::anyhow!("no skylink found in git config mapping or published index for {}", &download.object.oid))
             }
         }
     }
 
+    async fn download_writer(&self, object: &Object, writer: &mut (dyn AsyncWrite + Send + Unpin)) -> Result<()> {
+        let skylink = self.resolve_skylink(&object.oid).await?.ok_or_else(|| {
+            anyhow::anyhow!("no skylink found in git config mapping or published index for {}", &object.oid)
+        })?;
+
+        let _permit = self.limits.request_permit().await;
+        let mut response = self.portal_request("GET", &skylink).body(())?.send_async().await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("failed to download {}: {}", &skylink, response.status()));
+        }
+
+        throttle::copy_to(response.body_mut(), writer, self.limits.download.clone()).await?;
+
+        Ok(())
+    }
+
+    async fn upload_reader(&self, object: &Object, reader: ObjectReader) -> Result<()> {
+        let _permit = self.limits.request_permit().await;
+
+        self.upload_object(object, reader).await
+    }
+
     async fn upload(&self, upload: &Upload) -> Result<()> {
         log::debug!("received request to upload: {:#?}", &upload);
 
@@ -156,11 +654,15 @@ impl StorageProvider for SkynetProvider {
         // if no skylink mapping exists for the OID, or if the linked file is actually not available
         log::debug!("uploading {}...", &upload.path.display());
 
+        let _permit = self.limits.request_permit().await;
+
         match self.strategy {
-            UploadStrategy::Client => {
+            // skynet-rs reads the file itself and has no timeouts, so only our own upload can be
+            // throttled or time out
+            UploadStrategy::Client if self.limits.upload.is_none() && !self.http.has_timeouts() => {
                 self.upload_skynet_rs(upload).await
             }
-            UploadStrategy::CURL => {
+            _ => {
                 self.upload_isahc(upload).await
             }
         }
@@ -170,11 +672,8 @@ impl StorageProvider for SkynetProvider {
         // git object id
         let oid = &upload.object.oid;
 
-        // the git config keys cannot start with numbers or git will say its invalid
-        let map_key = Self::git_map_key(oid);
-
         // mapping exists
-        if let Some(skylink) = Self::get_skylink(oid) {
+        if let Some(skylink) = self.resolve_skylink(oid).await? {
             log::debug!("found OID => skylink mapping in git config");
 
             // decode skylink
@@ -183,36 +682,123 @@ impl StorageProvider for SkynetProvider {
 
             log::debug!("checking if file for Skylink {} is still available...", &skylink);
 
-            // check if file is still available
-            if let Ok(metadata) = self.client.get_metadata(skylink.as_str(), MetadataOptions::default()).await {
+            // check if file is still available and is the object
+            let stored = self.skyfile_metadata(&skylink).await.unwrap_or_else(|err| {
+                log::debug!("metadata request for {} failed: {:#}", &skylink, err);
+                None
+            });
+
+            if let Some((length, metadata)) = stored {
+                if !provider::matches_stored(&upload.object, length, &metadata) {
+                    // the mapping is overwritten by the upload that follows
+                    return Ok(false);
+                }
+
+                if let Some(mapping) = Self::get_mapping(oid)? {
+                    if self.is_due_for_renewal(&mapping, None) {
+                        let expiry = self.expires_at(&mapping)
+                            .map(|expires_at| format!("expires {}", expires_at.to_rfc3339()))
+                            .unwrap_or_else(|| "has no recorded upload time".to_string());
+
+                        log::warn!(
+                            "skylink {} for {} {}, run `git-lfs-web3 renew` to keep it pinned",
+                            &skylink, oid, &expiry
+                        );
+                    }
+                }
+
                 return Ok(true)
             }
 
-            // unset mapping
-            log::debug!("file no longer available. Unsetting mapping");
+            // the mapping is overwritten by the upload that follows
+            log::warn!("skylink {} for {} is no longer available", &skylink, oid);
+        }
+
+        Ok(false)
+    }
+
+    /// Objects without a mapping or index entry are known to be missing without asking the portal,
+    /// the metadata of the others is requested in parallel.
+    async fn is_uploaded_many(&self, objs: &[Upload]) -> Result<HashMap<String, bool>> {
+        let mut mapped = vec![];
+        let mut unmapped = vec![];
+
+        for obj in objs {
+            match self.resolve_skylink(&obj.object.oid).await? {
+                Some(_) => mapped.push(obj.clone()),
+                None => unmapped.push(obj.clone()),
+            }
+        }
+
+        let mut uploaded = provider::is_uploaded_concurrently(self, &mapped, METADATA_CHECK_JOBS).await;
+        uploaded.extend(unmapped.into_iter().map(|obj| (obj.object.oid, false)));
+
+        Ok(uploaded)
+    }
+
+    async fn list(&self) -> Result<Vec<RemoteObject>> {
+        Ok(Self::mappings()?
+            .into_iter()
+            .map(|mapping| RemoteObject {
+                oid: mapping.oid,
+                size: None,
+                uploaded_at: mapping.uploaded_at,
+                address: Some(mapping.skylink),
+            })
+            .collect())
+    }
+
+    async fn delete(&self, oid: &str) -> Result<()> {
+        let oid = oid.to_string();
+
+        if let Some(skylink) = Self::get_skylink(&oid)? {
+            let _permit = self.limits.request_permit().await;
 
-            // open git config
-            let mut gitconf = Self::git_config();
+            let response = self
+                .portal_request("POST", &format!("skynet/unpin/{}", &skylink))
+                .body(())?
+                .send_async()
+                .await?;
 
-            gitconf.remove(
-                map_key.as_str(),
-            );
+            // a skylink the portal doesn't know about anymore is as good as deleted
+            if !response.status().is_success() && response.status() != 404 {
+                return Err(anyhow::anyhow!("failed to unpin {}: {}", &skylink, response.status()));
+            }
+
+            log::debug!("unpinned {}", &skylink);
         }
 
-        Ok(false)
+        Self::git_remove_mapping(&oid)?;
+        self.removed.lock().unwrap().insert(oid);
+        self.index_changed.store(true, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// publish the mapping index when mappings changed and this repository publishes one
+    async fn finish(&self) -> Result<()> {
+        if !self.index_changed.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        match Self::registry_keypair()? {
+            Some(keypair) => self.publish_index(&keypair).await.map(|_| ()),
+            None => Ok(()),
+        }
     }
 }
 
 #[tokio::test]
-async fn save_skylink_mapping() {
+async fn save_skylink_mapping() -> Result<()> {
     // open git config
-    let mut gitconf = git2::Config::open(&Path::new(".git/config"))
-        .expect("failed to open git config file");
+    let mut gitconf = crate::repo::config()?;
     // save mapping
     gitconf.set_str(
         format!("lfs.customtransfer.skynet.mapping.testoid").as_str(),
         "testskylink"
-    ).expect("failed to write OID => Skylink mapping");
+    )?;
+
+    Ok(())
 }
 
 #[tokio::test]
@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use anyhow::{Context, Result};
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Digest};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+
+/// git config key of the secret seed of the keypair the index is published under, kept in the local config
+pub const REGISTRY_SEED_KEY: &str = "lfs-web3.skynet.registry.seed";
+
+/// git config key of the public key downloaders resolve the index with
+pub const REGISTRY_PUBLIC_KEY_KEY: &str = "lfs-web3.skynet.registry.publickey";
+
+/// git config key of the registry data key, to keep several repositories under one keypair apart
+pub const REGISTRY_DATA_KEY_KEY: &str = "lfs-web3.skynet.registry.datakey";
+
+pub const DEFAULT_DATA_KEY: &str = "git-lfs-web3/mapping-index";
+
+type Blake2b256 = Blake2b<U32>;
+
+/// the OID => Skylink table of a repository, as published in a skyfile
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct MappingIndex {
+    pub version: u32,
+    pub objects: BTreeMap<String, String>,
+}
+
+/// a registry entry as returned by the portal
+#[derive(Debug)]
+pub struct Entry {
+    pub data: Vec<u8>,
+    pub revision: u64,
+    pub signature: Vec<u8>,
+}
+
+fn hash_all(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Blake2b256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn encode_prefixed_bytes(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = (bytes.len() as u64).to_le_bytes().to_vec();
+    encoded.extend_from_slice(bytes);
+    encoded
+}
+
+/// the hash registry entries are looked up by, instead of the data key itself
+pub fn hash_data_key(data_key: &str) -> [u8; 32] {
+    hash_all(&[&encode_prefixed_bytes(data_key.as_bytes())])
+}
+
+/// the hash that is signed for an entry
+fn hash_entry(data_key: &str, data: &[u8], revision: u64) -> [u8; 32] {
+    hash_all(&[
+        &hash_data_key(data_key),
+        &encode_prefixed_bytes(data),
+        &revision.to_le_bytes(),
+    ])
+}
+
+/// the raw bytes of a skylink, which fit into a registry entry
+pub fn skylink_to_bytes(skylink: &str) -> Result<Vec<u8>> {
+    base64::decode_config(skylink.trim_start_matches("sia://"), base64::URL_SAFE_NO_PAD)
+        .with_context(|| format!("invalid skylink {}", skylink))
+}
+
+pub fn skylink_from_bytes(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// query string of the registry lookup for an entry
+pub fn lookup_query(public_key: &str, data_key: &str) -> String {
+    format!("publickey=ed25519:{}&datakey={}", public_key, hex::encode(hash_data_key(data_key)))
+}
+
+/// parse the JSON a portal answers a registry lookup with
+pub fn parse_entry(json: &serde_json::Value) -> Result<Entry> {
+    let field = |name: &str| {
+        json.get(name)
+            .and_then(|value| value.as_str())
+            .ok_or_else(|| anyhow::anyhow!("registry entry has no {}", name))
+            .and_then(|value| Ok(hex::decode(value)?))
+    };
+
+    Ok(Entry {
+        data: field("data")?,
+        revision: json
+            .get("revision")
+            .and_then(|revision| revision.as_u64())
+            .ok_or_else(|| anyhow::anyhow!("registry entry has no revision"))?,
+        signature: field("signature")?,
+    })
+}
+
+/// check that an entry was signed by the holder of the public key, the portal is not trusted with it
+pub fn verify_entry(public_key: &str, data_key: &str, entry: &Entry) -> Result<()> {
+    let public_key = PublicKey::from_bytes(&hex::decode(public_key)?)
+        .map_err(|err| anyhow::anyhow!("invalid registry public key: {}", err))?;
+    let signature = Signature::try_from(&entry.signature[..])
+        .map_err(|err| anyhow::anyhow!("invalid registry entry signature: {}", err))?;
+
+    public_key
+        .verify(&hash_entry(data_key, &entry.data, entry.revision), &signature)
+        .map_err(|_| anyhow::anyhow!("registry entry is not signed by {}", hex::encode(public_key.as_bytes())))
+}
+
+/// the keypair the mapping index of a repository is published under
+pub struct RegistryKeypair {
+    keypair: Keypair,
+}
+
+impl RegistryKeypair {
+    pub fn generate() -> Self {
+        Self {
+            keypair: Keypair::generate(&mut rand::rngs::OsRng),
+        }
+    }
+
+    pub fn from_seed(seed: &str) -> Result<Self> {
+        let secret = SecretKey::from_bytes(&hex::decode(seed.trim())?)
+            .map_err(|err| anyhow::anyhow!("invalid registry seed: {}", err))?;
+        let public = PublicKey::from(&secret);
+
+        Ok(Self {
+            keypair: Keypair { secret, public },
+        })
+    }
+
+    pub fn seed(&self) -> String {
+        hex::encode(self.keypair.secret.as_bytes())
+    }
+
+    pub fn public_key(&self) -> String {
+        hex::encode(self.keypair.public.as_bytes())
+    }
+
+    /// the body of the request that sets the entry to `data`
+    pub fn entry_request(&self, data_key: &str, data: &[u8], revision: u64) -> serde_json::Value {
+        let signature = self.keypair.sign(&hash_entry(data_key, data, revision));
+
+        serde_json::json!({
+            "publickey": {
+                "algorithm": "ed25519",
+                "key": self.keypair.public.as_bytes().to_vec(),
+            },
+            "datakey": hex::encode(hash_data_key(data_key)),
+            "revision": revision,
+            "data": data.to_vec(),
+            "signature": signature.to_bytes().to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const SKYLINK: &str = "AACogzrAimYPG42tDOKhS3lXZD8YvlF8Q8R17afe95iV2Q";
+
+    #[test]
+    fn skylink_round_trips_through_entry_bytes() {
+        let bytes = skylink_to_bytes(&format!("sia://{}", SKYLINK)).unwrap();
+
+        assert_eq!(bytes.len(), 34);
+        assert_eq!(skylink_from_bytes(&bytes), SKYLINK);
+    }
+
+    #[test]
+    fn signed_entry_verifies_only_for_its_key_and_data() {
+        let keypair = RegistryKeypair::generate();
+        let data = skylink_to_bytes(SKYLINK).unwrap();
+        let request = keypair.entry_request(DEFAULT_DATA_KEY, &data, 3);
+
+        let entry = parse_entry(&serde_json::json!({
+            "data": hex::encode(&data),
+            "revision": 3,
+            "signature": hex::encode(
+                request["signature"].as_array().unwrap().iter().map(|byte| byte.as_u64().unwrap() as u8).collect::<Vec<_>>()
+            ),
+        }))
+        .unwrap();
+
+        assert!(verify_entry(&keypair.public_key(), DEFAULT_DATA_KEY, &entry).is_ok());
+        assert!(verify_entry(&keypair.public_key(), "another-repository", &entry).is_err());
+        assert!(verify_entry(&RegistryKeypair::generate().public_key(), DEFAULT_DATA_KEY, &entry).is_err());
+    }
+
+    #[test]
+    fn keypair_is_restored_from_seed() {
+        let keypair = RegistryKeypair::generate();
+
+        assert_eq!(RegistryKeypair::from_seed(&keypair.seed()).unwrap().public_key(), keypair.public_key());
+    }
+}
@@ -1,33 +1,77 @@
+use std::collections::{BTreeSet, HashMap};
 use std::path::Path;
+use futures::StreamExt;
 use git_lfs_spec::transfer::custom::{Download, Upload};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use crate::provider::StorageProvider;
+use chrono::{DateTime, Utc};
+use crate::credentials::{self, CredentialSpec};
+use crate::http::HttpSettings;
+use crate::pointer;
+use crate::locks::{ConditionalLocks, ConditionalStore, Lock, LockStore};
+use crate::provider::{self, Capabilities, ObjectReader, RemoteObject, StorageProvider};
+use crate::throttle::{Limits, Throttled};
+use crate::tmp::PartialDownload;
+use git_lfs_spec::Object;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 use s3::bucket::Bucket;
 use s3::creds::Credentials;
 use s3::region::Region;
 
+const DEFAULT_ENDPOINT: &str = "https://gateway.storjshare.io";
+
+/// below this many objects single HEAD requests are cheaper than listing prefixes
+const LIST_CHECK_THRESHOLD: usize = 16;
+
+/// how many prefixes are listed at the same time
+const LIST_JOBS: usize = 8;
+
+/// how long the presigned URL of a download stays valid
+const PRESIGN_EXPIRY_SECS: u32 = 60 * 60;
+
+/// git config key of the key prefix this repository's objects and locks are kept under
+pub const PREFIX_CONFIG_KEY: &str = "lfs-web3.storj.prefix";
+
+const DEFAULT_BUCKET: &str = "parture-dev1";
+
+/// key prefix of the lock records, next to the objects
+const LOCK_PREFIX: &str = "locks/";
+
+/// settings read by the StorJ provider, see `credentials::resolve`
+pub const STORJ_CREDENTIALS: CredentialSpec = CredentialSpec {
+    provider: "storj",
+    vars: &["STORJ_S3_REGION", "STORJ_S3_ENDPOINT", "STORJ_S3_ACCESS_KEY", "STORJ_S3_SECRET", "STORJ_BUCKET"],
+    url_var: "STORJ_S3_ENDPOINT",
+    default_url: DEFAULT_ENDPOINT,
+    username_var: Some("STORJ_S3_ACCESS_KEY"),
+    password_var: "STORJ_S3_SECRET",
+};
+
 pub struct StorJProvider {
-    pub bucket: Bucket
+    pub bucket: Bucket,
+    /// Key prefix of this repository, empty or ending in `/`.
+    ///
+    /// Without one the objects live at the root of the bucket, next to those of every other
+    /// repository using it.
+    prefix: String,
+    locking: ConditionalLocks,
+    limits: Limits,
+    http: HttpSettings,
 }
 
 impl StorJProvider {
     pub fn new_from_env() -> Result<Self> {
         log::debug!("parsing storj env vars...");
 
-        let mut env_variables
-            = env_file_reader::read_file(".storj.env")?;
-
-        log::debug!("storj vars: {:#?}", &env_variables);
+        let mut env_variables = credentials::resolve(&STORJ_CREDENTIALS)?;
+        let http = HttpSettings::from_config();
 
-        Ok(Self {
-            bucket: Bucket::new(
-                // todo: make configurable
-                &"parture-dev1".to_string(),
+        let mut bucket = Bucket::new(
+                &env_variables.remove("STORJ_BUCKET").unwrap_or(DEFAULT_BUCKET.to_string()),
                 Region::Custom {
                     region: env_variables.remove("STORJ_S3_REGION").unwrap_or("eu1".to_string()),
-                    endpoint: env_variables.remove("STORJ_S3_ENDPOINT").unwrap_or("https://gateway.storjshare.io".to_string())
+                    endpoint: env_variables.remove("STORJ_S3_ENDPOINT").unwrap_or(DEFAULT_ENDPOINT.to_string())
                 },
                 Credentials::new(
                     env_variables.get("STORJ_S3_ACCESS_KEY").map(String::as_str),
@@ -36,12 +80,72 @@ impl StorJProvider {
                     None,
                     None
                 )?
-            )?
+            )?;
+        // the proxy and CA bundle reach rust-s3 through the environment, see `HttpSettings::export_env`
+        if http.timeout.is_some() {
+            bucket.set_request_timeout(http.timeout);
+        }
+
+        let prefix = crate::repo::config()
+            .and_then(|config| Ok(config.get_string(PREFIX_CONFIG_KEY)?))
+            .map(|prefix| Self::normalize_prefix(&prefix))
+            .unwrap_or_default();
+
+        log::debug!("using storj key prefix '{}'", &prefix);
+
+        Ok(Self {
+            bucket,
+            // next to the lock records, where it isn't taken for a lock or an object
+            locking: ConditionalLocks::new(format!("/{}lock-probe", &prefix)),
+            prefix,
+            limits: Limits::from_config("storj"),
+            http,
         })
     }
 
-    fn object_path(oid: &String) -> String {
-        format!("/{}", oid)
+    fn normalize_prefix(prefix: &str) -> String {
+        let prefix = prefix.trim_matches('/');
+
+        if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", prefix)
+        }
+    }
+
+    fn object_path(&self, oid: &str) -> String {
+        format!("/{}{}", &self.prefix, oid)
+    }
+
+    /// the sizes of the objects whose key starts with `prefix`
+    async fn sizes_with_prefix(&self, prefix: String) -> Result<Vec<(String, u64)>> {
+        let _permit = self.limits.request_permit().await;
+        let pages = self.bucket.list(format!("{}{}", &self.prefix, prefix), None).await?;
+
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .filter_map(|object| Some((self.key_oid(&object.key)?.to_string(), object.size)))
+            .collect())
+    }
+
+    fn lock_path(&self, id: &str) -> String {
+        format!("/{}{}{}", &self.prefix, LOCK_PREFIX, id)
+    }
+
+    async fn get_lock(&self, id: &str) -> Result<Option<Lock>> {
+        self.locking.get(self, &self.lock_path(id)).await
+    }
+
+    /// the oid an object key belongs to, if it is an LFS object of this repository at all
+    fn key_oid<'a>(&self, key: &'a str) -> Option<&'a str> {
+        let oid = key.trim_start_matches('/').strip_prefix(self.prefix.as_str())?;
+
+        if pointer::is_oid(oid) {
+            Some(oid)
+        } else {
+            None
+        }
     }
 }
 
@@ -53,34 +157,85 @@ impl Default for StorJProvider {
 
 #[async_trait]
 impl StorageProvider for StorJProvider {
-    async fn download(&self, download: &Download) -> anyhow::Result<String> {
-        // todo: generate temp file
-        let mut async_output_file = tokio::fs::File::create("/tmp/")
-            .await
-            .expect("Unable to create file");
+    fn name(&self) -> &'static str {
+        "storj"
+    }
 
-        // Async variant with `tokio` or `async-std` features
-        let status_code = self.bucket.get_object_stream(
-            Self::object_path(&download.object.oid),
-            &mut async_output_file
-        ).await?;
+    fn target(&self) -> String {
+        format!("storj:{}/{}/{}", self.bucket.region().endpoint(), self.bucket.name(), &self.prefix)
+    }
 
-        todo!()
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            needs_mapping: false,
+            supports_range: true,
+            supports_delete: true,
+            supports_list: true,
+            // the bucket may be shared with other repositories
+            scoped_list: !self.prefix.is_empty(),
+            // the S3 limit for a single object
+            max_object_size: Some(5 * 1024 * 1024 * 1024 * 1024),
+            recommended_concurrency: 8,
+        }
     }
 
-    async fn upload(&self, upload: &Upload) -> anyhow::Result<()> {
-        log::debug!("received request to upload: {:#?}", &upload);
+    async fn download(&self, download: &Download) -> anyhow::Result<String> {
+        let _permit = self.limits.request_permit().await;
+        let partial = PartialDownload::new(&download.object)?;
 
-        let oid = &upload.object.oid;
-        let objpath = StorJProvider::object_path(oid);
+        // a presigned URL, so interrupted downloads can be resumed with a range request
+        let url = self.bucket.presign_get(
+            self.object_path(&download.object.oid),
+            PRESIGN_EXPIRY_SECS,
+            None,
+        )?;
 
-        log::debug!("uploading {}...", &upload.path.display());
+        partial
+            .fetch(
+                self.http.configure(isahc::Request::get(url)),
+                self.limits.download.clone(),
+                self.capabilities().supports_range,
+            )
+            .await
+            .context("There was an error trying to download from storj portal")?;
 
-        let mut file = tokio::fs::File::open(upload.path.clone()).await?;
+        Ok(partial.complete().await?.to_string_lossy().to_string())
+    }
+
+    async fn download_writer(&self, object: &Object, writer: &mut (dyn AsyncWrite + Send + Unpin)) -> Result<()> {
+        let _permit = self.limits.request_permit().await;
+        let mut output = Throttled::new(writer, self.limits.download.clone());
 
         let status_code = self
             .bucket
-            .put_object_stream(&mut file, objpath)
+            .get_object_stream(self.object_path(&object.oid), &mut output)
+            .await?;
+
+        if status_code >= 300 {
+            return Err(anyhow::anyhow!("There was an error trying to download from storj portal: {:?}", status_code));
+        }
+
+        output.flush().await?;
+
+        Ok(())
+    }
+
+    async fn upload_reader(&self, object: &Object, reader: ObjectReader) -> Result<()> {
+        let oid = &object.oid;
+        let objpath = self.object_path(oid);
+
+        log::debug!("uploading {}...", oid);
+
+        let _permit = self.limits.request_permit().await;
+        let mut input = Throttled::new(reader, self.limits.upload.clone());
+
+        // stored with the object, so `is_uploaded` can tell a truncated upload apart
+        let mut bucket = self.bucket.clone();
+        bucket.add_header(&format!("x-amz-meta-{}", provider::OID_METADATA), oid);
+        bucket.add_header(&format!("x-amz-meta-{}", provider::SIZE_METADATA), &object.size.to_string());
+
+        let status_code = bucket
+            .put_object_stream(&mut input, objpath)
             .await?;
 
         if status_code < 300 {
@@ -94,17 +249,176 @@ impl StorageProvider for StorJProvider {
     }
 
     async fn is_uploaded(&self, obj: &Upload) -> Result<bool> {
+        let _permit = self.limits.request_permit().await;
+
         // check bucket to see whether file exists and length is the same
         let (head_object_result, code) = self
             .bucket
-            .head_object(StorJProvider::object_path(&obj.object.oid))
+            .head_object(self.object_path(&obj.object.oid))
             .await?;
 
-        // todo: check file len
-        // head_object_result.content_length == Some(upload.object.len)
+        if code >= 300 {
+            return Ok(false);
+        }
 
-        // whether upload for the file is needed
-        Ok(code < 300)
+        Ok(provider::matches_stored(
+            &obj.object,
+            head_object_result.content_length.map(|length| length as u64),
+            &head_object_result.metadata.unwrap_or_default(),
+        ))
+    }
+
+    /// Lists the two character oid prefixes of the objects instead of sending a HEAD request per object.
+    ///
+    /// Listings have no metadata, so only the objects whose size matches are asked for their
+    /// `x-amz-meta-oid` with a HEAD request, the others are known to be missing.
+    async fn is_uploaded_many(&self, objs: &[Upload]) -> Result<HashMap<String, bool>> {
+        if objs.len() < LIST_CHECK_THRESHOLD {
+            return Ok(provider::is_uploaded_concurrently(self, objs, LIST_JOBS).await);
+        }
+
+        let prefixes: BTreeSet<String> = objs
+            .iter()
+            .map(|obj| obj.object.oid.chars().take(2).collect())
+            .collect();
+
+        let listed: Vec<Result<Vec<(String, u64)>>> = futures::stream::iter(prefixes)
+            .map(|prefix| self.sizes_with_prefix(prefix))
+            .buffer_unordered(LIST_JOBS)
+            .collect()
+            .await;
+
+        let mut sizes = HashMap::new();
+        for objects in listed {
+            sizes.extend(objects?);
+        }
+
+        let (candidates, missing): (Vec<Upload>, Vec<Upload>) = objs
+            .iter()
+            .cloned()
+            .partition(|obj| sizes.get(&obj.object.oid) == Some(&obj.object.size));
+
+        let mut uploaded = provider::is_uploaded_concurrently(self, &candidates, LIST_JOBS).await;
+        uploaded.extend(missing.into_iter().map(|obj| (obj.object.oid, false)));
+
+        Ok(uploaded)
+    }
+
+    fn lock_store(&self) -> Option<&dyn LockStore> {
+        Some(self)
+    }
+
+    async fn list(&self) -> Result<Vec<RemoteObject>> {
+        let pages = self.bucket.list(self.prefix.clone(), None).await?;
+
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .filter_map(|object| {
+                let oid = self.key_oid(&object.key)?.to_string();
+
+                Some(RemoteObject {
+                    oid,
+                    size: Some(object.size),
+                    uploaded_at: DateTime::parse_from_rfc3339(&object.last_modified)
+                        .ok()
+                        .map(|time| time.with_timezone(&Utc)),
+                    address: Some(object.key),
+                })
+            })
+            .collect())
+    }
+
+    async fn delete(&self, oid: &str) -> Result<()> {
+        let _permit = self.limits.request_permit().await;
+
+        let response = self
+            .bucket
+            .delete_object(self.object_path(oid))
+            .await?;
+
+        // S3 answers 204 whether or not the object existed
+        if response.status_code() >= 300 {
+            return Err(anyhow::anyhow!("There was an error trying to delete from storj portal: {:?}", response.status_code()));
+        }
+
+        log::debug!("deleted {}", oid);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ConditionalStore for StorJProvider {
+    async fn put_if_absent(&self, key: &str, body: &[u8]) -> Result<bool> {
+        let mut bucket = self.bucket.clone();
+        bucket.add_header("If-None-Match", "*");
+
+        let response = bucket.put_object(key, body).await?;
+
+        match response.status_code() {
+            409 | 412 => Ok(false),
+            code if code >= 300 => Err(anyhow::anyhow!("There was an error trying to write {} to storj portal: {:?}", key, code)),
+            _ => Ok(true),
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let response = self.bucket.get_object(key).await?;
+
+        match response.status_code() {
+            404 => Ok(None),
+            code if code < 300 => Ok(Some(response.bytes().to_vec())),
+            code => Err(anyhow::anyhow!("There was an error trying to read {} from storj portal: {:?}", key, code)),
+        }
+    }
+}
+
+/// Locks are stored as JSON objects under `locks/<lock id>`.
+///
+/// Creating a lock is a conditional write (`If-None-Match: *`). Gateways that ignore the condition
+/// are detected before the first lock is created, and refused.
+#[async_trait]
+impl LockStore for StorJProvider {
+    async fn create_lock(&self, lock: &Lock) -> Result<Option<Lock>> {
+        let _permit = self.limits.request_permit().await;
+
+        self.locking.create(self, &self.lock_path(&lock.id), lock).await
+    }
+
+    async fn locks(&self) -> Result<Vec<Lock>> {
+        let _permit = self.limits.request_permit().await;
+
+        let ids: Vec<String> = self
+            .bucket
+            .list(format!("{}{}", &self.prefix, LOCK_PREFIX), None)
+            .await?
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .filter_map(|object| object.key.rsplit('/').next().map(str::to_string))
+            .collect();
+
+        let mut locks = vec![];
+        for id in ids {
+            // released while we were listing
+            if let Some(lock) = self.get_lock(&id).await? {
+                locks.push(lock);
+            }
+        }
+
+        Ok(locks)
+    }
+
+    async fn remove_lock(&self, id: &str) -> Result<()> {
+        let _permit = self.limits.request_permit().await;
+
+        let response = self.bucket.delete_object(self.lock_path(id)).await?;
+
+        if response.status_code() >= 300 {
+            return Err(anyhow::anyhow!("There was an error trying to remove lock {} from storj portal: {:?}", id, response.status_code()));
+        }
+
+        Ok(())
     }
 }
 
@@ -126,7 +440,10 @@ async fn test_storj_bucket_head() {
 async fn test_storj_bucket_upload() {
     let bucket = StorJProvider::default().bucket;
 
-    let mut file = tokio::fs::File::open(".git/lfs/objects/67/da/67da1154a858aa2d89f5201246f6d4b43a0a4eb55011136e993728f0daf8703e").await.unwrap();
+    let repo = crate::repo::open().unwrap();
+    let path = crate::pointer::local_object_path(&repo, "67da1154a858aa2d89f5201246f6d4b43a0a4eb55011136e993728f0daf8703e");
+
+    let mut file = tokio::fs::File::open(path).await.unwrap();
 
     bucket.put_object_stream(&mut file, "/67da1154a858aa2d89f5201246f6d4b43a0a4eb55011136e993728f0daf8703e").await.unwrap();
 }
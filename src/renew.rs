@@ -0,0 +1,46 @@
+use anyhow::Result;
+
+use crate::pointer;
+use crate::providers::{Renewal, SkynetProvider, UploadStrategy};
+use crate::repo;
+
+/// Re-pin every skylink that is close to being unpinned by the portal.
+///
+/// Skylinks the portal can't pin anymore are uploaded again from the local copy when there is one.
+pub async fn renew(renew_days: Option<i64>, dry_run: bool) -> Result<bool> {
+    let client = SkynetProvider::new_from_env(UploadStrategy::Client)?;
+    let repo = repo::open()?;
+
+    let due: Vec<_> = SkynetProvider::mappings()?
+        .into_iter()
+        .filter(|mapping| client.is_due_for_renewal(mapping, renew_days))
+        .collect();
+
+    let mut failed = 0;
+
+    for mapping in &due {
+        let expiry = client.expires_at(mapping)
+            .map(|expires_at| expires_at.to_rfc3339())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        if dry_run {
+            println!("due     {} {} (expires {})", mapping.oid, mapping.skylink, expiry);
+            continue;
+        }
+
+        let local_path = pointer::local_object_path(&repo, &mapping.oid);
+
+        match client.renew(mapping, &local_path).await {
+            Ok(Renewal::Pinned) => println!("pinned  {} {}", mapping.oid, mapping.skylink),
+            Ok(Renewal::Reuploaded) => println!("uploaded {} again", mapping.oid),
+            Err(err) => {
+                failed += 1;
+                println!("failed  {}: {:#}", mapping.oid, err);
+            }
+        }
+    }
+
+    println!("{} skylinks due for renewal, {} failed", due.len(), failed);
+
+    Ok(failed == 0)
+}
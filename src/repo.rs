@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+use anyhow::{Context, Result};
+use git2::Repository;
+
+/// Open the repository we run in the same way git does.
+///
+/// Honours `GIT_DIR` and friends, and finds the repository from linked worktrees,
+/// submodules (where `.git` is a file) and bare repositories.
+pub fn open() -> Result<Repository> {
+    Repository::open_from_env().context("not inside a git repository")
+}
+
+/// config of the repository, including the user and system levels.
+/// Writes end up in the repository's own config, which linked worktrees share
+pub fn config() -> Result<git2::Config> {
+    Ok(open()?.config()?)
+}
+
+/// the git dir shared by all worktrees of a repository
+pub fn common_dir(repo: &Repository) -> PathBuf {
+    let git_dir = repo.path();
+
+    match std::fs::read_to_string(git_dir.join("commondir")) {
+        Ok(common_dir) => {
+            let common_dir = PathBuf::from(common_dir.trim());
+
+            if common_dir.is_absolute() {
+                common_dir
+            } else {
+                git_dir.join(common_dir)
+            }
+        }
+        Err(_) => git_dir.to_path_buf(),
+    }
+}
+
+/// where git-lfs keeps its objects and temp files for a repository, `lfs.storage` or `<common dir>/lfs`
+pub fn lfs_dir(repo: &Repository) -> PathBuf {
+    let common_dir = common_dir(repo);
+
+    match repo.config().and_then(|config| config.get_path("lfs.storage")) {
+        Ok(storage) if storage.is_absolute() => storage,
+        Ok(storage) => common_dir.join(storage),
+        Err(_) => common_dir.join("lfs"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn init_with_commit(path: &std::path::Path) -> Repository {
+        let repo = Repository::init(path).unwrap();
+        {
+            let tree_id = repo.index().unwrap().write_tree().unwrap();
+            let tree = repo.find_tree(tree_id).unwrap();
+            let signature = git2::Signature::now("test", "test@example.com").unwrap();
+            repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[]).unwrap();
+        }
+        repo
+    }
+
+    #[test]
+    fn common_dir_of_linked_worktree_is_main_git_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_with_commit(&dir.path().join("main"));
+
+        let worktree = repo.worktree("linked", &dir.path().join("linked"), None).unwrap();
+        let linked = Repository::open_from_worktree(&worktree).unwrap();
+
+        assert_eq!(
+            common_dir(&linked).canonicalize().unwrap(),
+            repo.path().canonicalize().unwrap()
+        );
+        assert_eq!(
+            lfs_dir(&linked).parent().unwrap().canonicalize().unwrap(),
+            repo.path().canonicalize().unwrap()
+        );
+    }
+
+    #[test]
+    fn lfs_dir_honours_lfs_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_with_commit(dir.path());
+
+        repo.config().unwrap().set_str("lfs.storage", "elsewhere").unwrap();
+
+        assert_eq!(lfs_dir(&repo), repo.path().join("elsewhere"));
+    }
+}
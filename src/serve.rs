@@ -0,0 +1,691 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use anyhow::Result;
+use git_lfs_spec::Object;
+use git_lfs_spec::transfer::custom::{Download, Upload};
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE, HOST};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::locks::{Lock, LockStore};
+use crate::pointer;
+use crate::provider::StorageProvider;
+use crate::providers;
+use crate::tmp::PartialDownload;
+use crate::upload_index::Indexed;
+
+const LFS_CONTENT_TYPE: &str = "application/vnd.git-lfs+json";
+
+/// the endpoints of the locking API
+#[derive(Debug, PartialEq)]
+enum LockRoute {
+    Locks,
+    Verify,
+    Unlock(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateLockRequest {
+    path: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct UnlockRequest {
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Download,
+    Upload,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ObjectSpec {
+    pub oid: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    pub operation: Operation,
+    #[serde(default)]
+    pub transfers: Vec<String>,
+    pub objects: Vec<ObjectSpec>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchResponse {
+    pub transfer: &'static str,
+    pub objects: Vec<ObjectResponse>,
+    pub hash_algo: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ObjectResponse {
+    pub oid: String,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actions: Option<Actions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ObjectError>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Actions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download: Option<Action>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload: Option<Action>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Action {
+    pub href: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ObjectError {
+    pub code: u16,
+    pub message: String,
+}
+
+/// Serve the Git LFS Batch API and basic transfers for the configured provider.
+///
+/// Runs until interrupted, then lets the provider publish its state like after a transfer.
+pub async fn serve(addr: SocketAddr) -> Result<()> {
+    // nothing is authenticated, lock owners included, so only the local user may connect
+    if !addr.ip().is_loopback() {
+        return Err(anyhow::anyhow!(
+            "refusing to listen on {}: the server has no authentication, only loopback addresses are allowed",
+            addr
+        ));
+    }
+
+    let client: Arc<dyn StorageProvider + Send> = Arc::new(Indexed::from_config(providers::from_config()?, false));
+    let name = client.name();
+
+    let make_service = {
+        let client = client.clone();
+
+        make_service_fn(move |_connection| {
+            let client = client.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let client = client.clone();
+
+                    async move { Ok::<_, Infallible>(handle(&*client, request).await) }
+                }))
+            }
+        })
+    };
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+
+    println!("serving Git LFS objects on {} at http://{}", name, addr);
+    log::info!("serving Git LFS objects on {} at http://{}", name, addr);
+
+    server
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+
+    client.finish().await
+}
+
+async fn handle(client: &dyn StorageProvider, request: Request<Body>) -> Response<Body> {
+    log::debug!("{} {}", request.method(), request.uri());
+
+    match route(client, request).await {
+        Ok(response) => response,
+        Err(err) => {
+            log::error!("request failed: {:#}", err);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, &format!("{:#}", err))
+        }
+    }
+}
+
+async fn route(client: &dyn StorageProvider, request: Request<Body>) -> Result<Response<Body>> {
+    let path = request.uri().path().to_string();
+
+    if let Some(prefix) = path.strip_suffix("/objects/batch") {
+        if request.method() == Method::POST {
+            let base_url = base_url(&request, prefix);
+            return batch(client, &base_url, request.into_body()).await;
+        }
+    }
+
+    if let Some(route) = parse_lock_path(&path) {
+        let store = match client.lock_store() {
+            Some(store) => store,
+            None => return Ok(error_response(
+                StatusCode::NOT_IMPLEMENTED,
+                &format!("{} can't hold locks", client.name()),
+            )),
+        };
+
+        // git-lfs asks its credential helper when challenged, the user name identifies the lock owner
+        let user = match basic_auth_user(&request) {
+            Some(user) => user,
+            None => {
+                let mut response = error_response(StatusCode::UNAUTHORIZED, "a user name is needed to lock files");
+                response.headers_mut().insert(
+                    "LFS-Authenticate",
+                    hyper::header::HeaderValue::from_static("Basic realm=\"git-lfs-web3\""),
+                );
+                return Ok(response);
+            }
+        };
+
+        let method = request.method().clone();
+        let query = request.uri().query().unwrap_or_default().to_string();
+        let body = hyper::body::to_bytes(request.into_body()).await?;
+
+        return locks(store, &user, route, &method, &query, &body).await;
+    }
+
+    if let Some((oid, size)) = parse_object_path(&path) {
+        match *request.method() {
+            Method::GET => return download(client, oid, size).await,
+            Method::PUT => return upload(client, oid, size, request.into_body()).await,
+            _ => {}
+        }
+    }
+
+    Ok(error_response(StatusCode::NOT_FOUND, "not found"))
+}
+
+/// the URL the endpoints are reachable under, as seen by the client
+fn base_url(request: &Request<Body>, prefix: &str) -> String {
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or("127.0.0.1");
+
+    format!("http://{}{}", host, prefix)
+}
+
+/// the oid and size of an object transfer path, `<prefix>/objects/<oid>/<size>`
+fn parse_object_path(path: &str) -> Option<(String, u64)> {
+    let (_, object) = path.rsplit_once("/objects/")?;
+    let (oid, size) = object.split_once('/')?;
+
+    if !pointer::is_oid(oid) {
+        return None;
+    }
+
+    Some((oid.to_string(), size.parse().ok()?))
+}
+
+fn parse_lock_path(path: &str) -> Option<LockRoute> {
+    if path.ends_with("/locks") {
+        return Some(LockRoute::Locks);
+    }
+
+    if path.ends_with("/locks/verify") {
+        return Some(LockRoute::Verify);
+    }
+
+    let (_, id) = path.strip_suffix("/unlock")?.rsplit_once("/locks/")?;
+    Some(LockRoute::Unlock(id.to_string()))
+}
+
+/// the user name of HTTP basic auth, the password isn't checked
+fn basic_auth_user(request: &Request<Body>) -> Option<String> {
+    let credentials = request
+        .headers()
+        .get(hyper::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+
+    let credentials = String::from_utf8(base64::decode(credentials.trim()).ok()?).ok()?;
+    let (user, _) = credentials.split_once(':')?;
+
+    Some(user.to_string()).filter(|user| !user.is_empty())
+}
+
+/// the decoded value of a query parameter
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let escaped = std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+            if let Some(byte) = escaped {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+
+        decoded.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+async fn locks(
+    store: &dyn LockStore,
+    user: &str,
+    route: LockRoute,
+    method: &Method,
+    query: &str,
+    body: &[u8],
+) -> Result<Response<Body>> {
+    match (route, method) {
+        (LockRoute::Locks, &Method::POST) => {
+            let request: CreateLockRequest = match serde_json::from_slice(body) {
+                Ok(request) => request,
+                Err(err) => return Ok(error_response(StatusCode::UNPROCESSABLE_ENTITY, &format!("invalid lock request: {}", err))),
+            };
+
+            let lock = Lock::new(&request.path, user);
+
+            match store.create_lock(&lock).await? {
+                None => {
+                    log::info!("{} locked {}", user, &lock.path);
+                    json_response(StatusCode::CREATED, &serde_json::json!({ "lock": lock }))
+                }
+                Some(existing) => json_response(StatusCode::CONFLICT, &serde_json::json!({
+                    "lock": existing,
+                    "message": format!("{} is already locked by {}", &existing.path, &existing.owner.name),
+                })),
+            }
+        }
+        (LockRoute::Locks, &Method::GET) => {
+            let path = query_param(query, "path");
+            let id = query_param(query, "id");
+
+            let locks: Vec<Lock> = store
+                .locks()
+                .await?
+                .into_iter()
+                .filter(|lock| path.as_ref().map_or(true, |path| &lock.path == path))
+                .filter(|lock| id.as_ref().map_or(true, |id| &lock.id == id))
+                .collect();
+
+            json_response(StatusCode::OK, &serde_json::json!({ "locks": locks }))
+        }
+        (LockRoute::Verify, &Method::POST) => {
+            let (ours, theirs): (Vec<Lock>, Vec<Lock>) = store
+                .locks()
+                .await?
+                .into_iter()
+                .partition(|lock| lock.owner.name == user);
+
+            json_response(StatusCode::OK, &serde_json::json!({ "ours": ours, "theirs": theirs }))
+        }
+        (LockRoute::Unlock(id), &Method::POST) => {
+            let request: UnlockRequest = serde_json::from_slice(body).unwrap_or_default();
+
+            let lock = match store.locks().await?.into_iter().find(|lock| lock.id == id) {
+                Some(lock) => lock,
+                None => return Ok(error_response(StatusCode::NOT_FOUND, &format!("no lock with id {}", id))),
+            };
+
+            if lock.owner.name != user && !request.force {
+                return Ok(error_response(
+                    StatusCode::FORBIDDEN,
+                    &format!("{} is locked by {}, use --force to unlock it anyway", &lock.path, &lock.owner.name),
+                ));
+            }
+
+            store.remove_lock(&id).await?;
+            log::info!("{} unlocked {}", user, &lock.path);
+
+            json_response(StatusCode::OK, &serde_json::json!({ "lock": lock }))
+        }
+        _ => Ok(error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")),
+    }
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, LFS_CONTENT_TYPE)
+        .body(Body::from(serde_json::to_vec(body)?))?)
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let body = serde_json::json!({ "message": message }).to_string();
+
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, hyper::header::HeaderValue::from_static(LFS_CONTENT_TYPE));
+    response
+}
+
+async fn batch(client: &dyn StorageProvider, base_url: &str, body: Body) -> Result<Response<Body>> {
+    let body = hyper::body::to_bytes(body).await?;
+
+    let request: BatchRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(err) => return Ok(error_response(StatusCode::UNPROCESSABLE_ENTITY, &format!("invalid batch request: {}", err))),
+    };
+
+    if !request.transfers.is_empty() && !request.transfers.iter().any(|transfer| transfer == "basic") {
+        return Ok(error_response(StatusCode::UNPROCESSABLE_ENTITY, "only the basic transfer adapter is supported"));
+    }
+
+    json_response(StatusCode::OK, &batch_response(client, base_url, request).await?)
+}
+
+/// Answer a batch request: uploads for objects the provider doesn't have yet, downloads for the ones it has.
+pub async fn batch_response(client: &dyn StorageProvider, base_url: &str, request: BatchRequest) -> Result<BatchResponse> {
+    let uploads: Vec<Upload> = request
+        .objects
+        .iter()
+        .filter(|object| pointer::is_oid(&object.oid))
+        .map(|object| Upload {
+            object: Object {
+                oid: object.oid.clone(),
+                size: object.size,
+            },
+            path: Default::default(),
+        })
+        .collect();
+
+    let present = client.is_uploaded_many(&uploads).await?;
+
+    let objects = request
+        .objects
+        .into_iter()
+        .map(|object| {
+            let uploaded = present.get(&object.oid) == Some(&true);
+            let action = || Some(Action {
+                href: format!("{}/objects/{}/{}", base_url, &object.oid, object.size),
+            });
+
+            let (actions, error) = match (request.operation, uploaded) {
+                _ if !pointer::is_oid(&object.oid) => (None, Some(ObjectError {
+                    code: 422,
+                    message: "the oid is not a lowercase sha256".to_string(),
+                })),
+                // nothing to do, git-lfs considers the object uploaded
+                (Operation::Upload, true) => (None, None),
+                (Operation::Upload, false) => (Some(Actions { upload: action(), ..Default::default() }), None),
+                (Operation::Download, true) => (Some(Actions { download: action(), ..Default::default() }), None),
+                (Operation::Download, false) => (None, Some(ObjectError {
+                    code: 404,
+                    message: format!("{} does not hold this object", client.name()),
+                })),
+            };
+
+            ObjectResponse {
+                oid: object.oid,
+                size: object.size,
+                actions,
+                error,
+            }
+        })
+        .collect();
+
+    Ok(BatchResponse {
+        transfer: "basic",
+        objects,
+        hash_algo: "sha256",
+    })
+}
+
+async fn download(client: &dyn StorageProvider, oid: String, size: u64) -> Result<Response<Body>> {
+    let path = match client.download(&Download { object: Object { oid: oid.clone(), size } }).await {
+        Ok(path) => path,
+        Err(err) => {
+            log::warn!("could not download {}: {:#}", &oid, err);
+            return Ok(error_response(StatusCode::NOT_FOUND, &format!("could not download {}", &oid)));
+        }
+    };
+
+    let mut file = tokio::fs::File::open(&path).await?;
+    let (mut sender, body) = Body::channel();
+
+    // stream the file from a task, so large objects don't have to fit in memory
+    tokio::spawn(async move {
+        let mut buffer = vec![0u8; 64 * 1024];
+
+        loop {
+            match file.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(read) => {
+                    if sender.send_data(Bytes::copy_from_slice(&buffer[..read])).await.is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    log::error!("could not read {}: {}", &path, err);
+                    sender.abort();
+                    break;
+                }
+            }
+        }
+
+        let _ = tokio::fs::remove_file(&path).await;
+    });
+
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "application/octet-stream")
+        .header(CONTENT_LENGTH, size)
+        .body(body)?)
+}
+
+async fn upload(client: &dyn StorageProvider, oid: String, size: u64, mut body: Body) -> Result<Response<Body>> {
+    let object = Object { oid, size };
+
+    // received like a download, so the content is checked against the oid before it is stored
+    let partial = PartialDownload::new(&object)?;
+    // a failed upload can't be resumed, the client sends the object again
+    partial.restart()?;
+    let mut file = partial.tokio_file()?;
+
+    while let Some(chunk) = body.data().await {
+        file.write_all(&chunk?).await?;
+    }
+    file.flush().await?;
+
+    let path = match partial.complete().await {
+        Ok(path) => path,
+        Err(err) => return Ok(error_response(StatusCode::UNPROCESSABLE_ENTITY, &format!("{:#}", err))),
+    };
+
+    let result = client.upload(&Upload { object, path: path.clone() }).await;
+    let _ = tokio::fs::remove_file(&path).await;
+    result?;
+
+    Ok(Response::new(Body::empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use pretty_assertions::assert_eq;
+    use crate::provider::RemoteObject;
+
+    const OID: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+    const OTHER_OID: &str = "a948904f2f0f479b8f8197694b30184b0d2ed1c1cd2a1ec0fb85d299a192a447";
+
+    /// a provider that holds a fixed set of objects
+    struct FixedProvider(HashSet<String>);
+
+    #[async_trait::async_trait]
+    impl StorageProvider for FixedProvider {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        async fn download_writer(
+            &self,
+            _object: &Object,
+            _writer: &mut (dyn tokio::io::AsyncWrite + Send + Unpin),
+        ) -> anyhow::Result<()> {
+            Err(anyhow::anyhow!("not implemented"))
+        }
+
+        async fn upload_reader(&self, _object: &Object, _reader: crate::provider::ObjectReader) -> anyhow::Result<()> {
+            Err(anyhow::anyhow!("not implemented"))
+        }
+
+        async fn is_uploaded(&self, upload: &Upload) -> anyhow::Result<bool> {
+            Ok(self.0.contains(&upload.object.oid))
+        }
+
+        async fn list(&self) -> anyhow::Result<Vec<RemoteObject>> {
+            Ok(vec![])
+        }
+
+        async fn delete(&self, _oid: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn request(operation: Operation) -> BatchRequest {
+        BatchRequest {
+            operation,
+            transfers: vec!["basic".to_string()],
+            objects: vec![
+                ObjectSpec { oid: OID.to_string(), size: 11 },
+                ObjectSpec { oid: OTHER_OID.to_string(), size: 12 },
+            ],
+        }
+    }
+
+    #[test]
+    fn parse_object_path_accepts_prefixed_paths() {
+        assert_eq!(
+            parse_object_path(&format!("/repo.git/info/lfs/objects/{}/11", OID)),
+            Some((OID.to_string(), 11))
+        );
+        assert_eq!(parse_object_path(&format!("/objects/{}", OID)), None);
+        assert_eq!(parse_object_path("/objects/batch/11"), None);
+        assert_eq!(parse_object_path(&format!("/objects/{}/11", OID.to_uppercase())), None);
+    }
+
+    #[tokio::test]
+    async fn batch_upload_only_asks_for_missing_objects() {
+        let client = FixedProvider(vec![OID.to_string()].into_iter().collect());
+
+        let response = batch_response(&client, "http://127.0.0.1:8080", request(Operation::Upload)).await.unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&response).unwrap()["objects"],
+            serde_json::json!([
+                { "oid": OID, "size": 11 },
+                {
+                    "oid": OTHER_OID,
+                    "size": 12,
+                    "actions": { "upload": { "href": format!("http://127.0.0.1:8080/objects/{}/12", OTHER_OID) } }
+                },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_download_reports_missing_objects() {
+        let client = FixedProvider(vec![OID.to_string()].into_iter().collect());
+
+        let response = batch_response(&client, "http://127.0.0.1:8080", request(Operation::Download)).await.unwrap();
+
+        assert!(response.objects[0].actions.as_ref().unwrap().download.is_some());
+        assert_eq!(response.objects[1].error.as_ref().unwrap().code, 404);
+    }
+
+    #[tokio::test]
+    async fn batch_rejects_uppercase_oids() {
+        let client = FixedProvider(vec![OID.to_uppercase()].into_iter().collect());
+        let mut request = request(Operation::Download);
+        request.objects[0].oid = OID.to_uppercase();
+
+        let response = batch_response(&client, "http://127.0.0.1:8080", request).await.unwrap();
+
+        assert!(response.objects[0].actions.is_none());
+        assert_eq!(response.objects[0].error.as_ref().unwrap().code, 422);
+    }
+
+    /// a lock store in memory
+    #[derive(Default)]
+    struct MemoryLocks(std::sync::Mutex<Vec<Lock>>);
+
+    #[async_trait::async_trait]
+    impl LockStore for MemoryLocks {
+        async fn create_lock(&self, lock: &Lock) -> anyhow::Result<Option<Lock>> {
+            let mut locks = self.0.lock().unwrap();
+
+            if let Some(existing) = locks.iter().find(|existing| existing.id == lock.id) {
+                return Ok(Some(existing.clone()));
+            }
+
+            locks.push(lock.clone());
+            Ok(None)
+        }
+
+        async fn locks(&self) -> anyhow::Result<Vec<Lock>> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+
+        async fn remove_lock(&self, id: &str) -> anyhow::Result<()> {
+            self.0.lock().unwrap().retain(|lock| lock.id != id);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn parse_lock_path_recognizes_endpoints() {
+        assert_eq!(parse_lock_path("/repo.git/info/lfs/locks"), Some(LockRoute::Locks));
+        assert_eq!(parse_lock_path("/locks/verify"), Some(LockRoute::Verify));
+        assert_eq!(parse_lock_path("/locks/abc/unlock"), Some(LockRoute::Unlock("abc".to_string())));
+        assert_eq!(parse_lock_path(&format!("/objects/{}/11", OID)), None);
+    }
+
+    #[test]
+    fn query_param_is_percent_decoded() {
+        assert_eq!(query_param("limit=10&path=assets%2Fhero%20v2.psd", "path"), Some("assets/hero v2.psd".to_string()));
+        assert_eq!(query_param("limit=10", "path"), None);
+    }
+
+    #[tokio::test]
+    async fn locks_conflict_and_only_owner_unlocks_without_force() {
+        let store = MemoryLocks::default();
+        let body = br#"{"path":"assets/hero.psd"}"#;
+
+        let created = locks(&store, "alice", LockRoute::Locks, &Method::POST, "", body).await.unwrap();
+        assert_eq!(created.status(), StatusCode::CREATED);
+
+        let conflict = locks(&store, "bob", LockRoute::Locks, &Method::POST, "", body).await.unwrap();
+        assert_eq!(conflict.status(), StatusCode::CONFLICT);
+
+        let id = crate::locks::lock_id("assets/hero.psd");
+
+        let forbidden = locks(&store, "bob", LockRoute::Unlock(id.clone()), &Method::POST, "", b"{}").await.unwrap();
+        assert_eq!(forbidden.status(), StatusCode::FORBIDDEN);
+
+        let forced = locks(&store, "bob", LockRoute::Unlock(id), &Method::POST, "", br#"{"force":true}"#).await.unwrap();
+        assert_eq!(forced.status(), StatusCode::OK);
+        assert!(store.locks().await.unwrap().is_empty());
+    }
+}
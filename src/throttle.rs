@@ -0,0 +1,237 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use futures::ready;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Sleep;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+use crate::repo;
+
+/// A token bucket limiting the bytes per second passing through it.
+///
+/// The bucket holds at most one second worth of bytes, so bursts stay short.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_second: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second as f64,
+            bucket: Mutex::new(Bucket {
+                tokens: bytes_per_second as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// take `bytes` out of the bucket, returning how long to wait until the bucket has recovered
+    pub fn reserve(&self, bytes: usize) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+
+        let refill = now.duration_since(bucket.updated).as_secs_f64() * self.bytes_per_second;
+        bucket.tokens = (bucket.tokens + refill).min(self.bytes_per_second);
+        bucket.updated = now;
+        bucket.tokens -= bytes as f64;
+
+        if bucket.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / self.bytes_per_second)
+        }
+    }
+}
+
+/// the limits configured for a provider
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    pub upload: Option<Arc<RateLimiter>>,
+    pub download: Option<Arc<RateLimiter>>,
+    requests: Option<Arc<Semaphore>>,
+}
+
+impl Limits {
+    /// Read the limits for a provider from git config.
+    ///
+    /// `lfs-web3.<provider>.<setting>` takes precedence over `lfs-web3.<setting>`, for the settings
+    /// `uploadlimit` and `downloadlimit` (bytes per second, `k`, `m` and `g` suffixes allowed)
+    /// and `maxrequests` (concurrent requests).
+    pub fn from_config(provider: &str) -> Self {
+        let config = match repo::config() {
+            Ok(config) => config,
+            Err(_) => return Self::default(),
+        };
+
+        let setting = |name: &str| {
+            config
+                .get_i64(&format!("lfs-web3.{}.{}", provider, name))
+                .or_else(|_| config.get_i64(&format!("lfs-web3.{}", name)))
+                .ok()
+                .filter(|value| *value > 0)
+        };
+
+        let limits = Self {
+            upload: setting("uploadlimit").map(|limit| Arc::new(RateLimiter::new(limit as u64))),
+            download: setting("downloadlimit").map(|limit| Arc::new(RateLimiter::new(limit as u64))),
+            requests: setting("maxrequests").map(|max| Arc::new(Semaphore::new(max as usize))),
+        };
+
+        log::debug!("{} limits: {:?}", provider, &limits);
+
+        limits
+    }
+
+    /// wait until another request to the provider is allowed, the permit is held until dropped
+    pub async fn request_permit(&self) -> Option<OwnedSemaphorePermit> {
+        match &self.requests {
+            Some(requests) => requests.clone().acquire_owned().await.ok(),
+            None => None,
+        }
+    }
+}
+
+/// an async reader or writer whose throughput is limited by a `RateLimiter`
+pub struct Throttled<T> {
+    inner: T,
+    limiter: Option<Arc<RateLimiter>>,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<T> Throttled<T> {
+    pub fn new(inner: T, limiter: Option<Arc<RateLimiter>>) -> Self {
+        Self {
+            inner,
+            limiter,
+            delay: None,
+        }
+    }
+
+    /// wait out the delay caused by the previous read or write
+    fn poll_delay(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(delay) = self.delay.as_mut() {
+            ready!(delay.as_mut().poll(cx));
+            self.delay = None;
+        }
+        Poll::Ready(())
+    }
+
+    fn consumed(&mut self, bytes: usize) {
+        if let Some(limiter) = &self.limiter {
+            let wait = limiter.reserve(bytes);
+
+            if wait > Duration::from_secs(0) {
+                self.delay = Some(Box::pin(tokio::time::sleep(wait)));
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Throttled<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_delay(cx));
+
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.consumed(buf.filled().len() - before);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Throttled<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_delay(cx));
+
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.consumed(written);
+
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// copy everything from an HTTP response body to `writer`, throttled by `limiter`
+pub async fn copy_to(
+    mut reader: impl futures::io::AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+    limiter: Option<Arc<RateLimiter>>,
+) -> io::Result<u64> {
+    use futures::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+
+    let mut output = Throttled::new(writer, limiter);
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut copied = 0;
+
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        output.write_all(&buffer[..read]).await?;
+        copied += read as u64;
+    }
+
+    output.flush().await?;
+
+    Ok(copied)
+}
+
+/// an HTTP request body streaming `size` bytes from `reader`, throttled by `limiter`
+pub fn body_from<R>(reader: R, size: u64, limiter: Option<Arc<RateLimiter>>) -> isahc::AsyncBody
+where
+    R: AsyncRead + Send + Sync + Unpin + 'static,
+{
+    isahc::AsyncBody::from_reader_sized(Throttled::new(reader, limiter).compat(), size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserve_allows_one_second_burst_then_waits() {
+        let limiter = RateLimiter::new(1000);
+
+        assert_eq!(limiter.reserve(1000), Duration::from_secs(0));
+
+        let wait = limiter.reserve(500);
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500), "{:?}", wait);
+    }
+
+    #[tokio::test]
+    async fn throttled_reader_passes_all_bytes() {
+        use tokio::io::AsyncReadExt;
+
+        let input = vec![7u8; 4096];
+        let mut reader = Throttled::new(&input[..], Some(Arc::new(RateLimiter::new(1024 * 1024))));
+        let mut output = vec![];
+
+        reader.read_to_end(&mut output).await.unwrap();
+
+        assert_eq!(output, input);
+    }
+}
@@ -0,0 +1,386 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use anyhow::{Context, Result};
+use fs2::FileExt;
+use futures::io::AsyncRead;
+use git_lfs_spec::Object;
+use isahc::http::StatusCode;
+use isahc::RequestExt;
+
+use crate::oid::oid_of_file;
+use crate::repo;
+use crate::throttle::{self, RateLimiter};
+
+/// suffix of downloads that haven't been verified yet
+const PARTIAL_SUFFIX: &str = ".part";
+
+/// Suffix of the lock file of a resumable download.
+///
+/// The transfer writing to the download holds an advisory lock on it, which the OS releases
+/// when the process dies, so a crashed transfer doesn't keep the next one from resuming.
+const LOCK_SUFFIX: &str = ".lock";
+
+/// partial downloads older than this are left over from crashed transfers
+const STALE_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// the temp dir git-lfs uses for the repository, `<lfs dir>/tmp`
+pub fn tmp_dir() -> Result<PathBuf> {
+    let dir = repo::lfs_dir(&repo::open()?).join("tmp");
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("could not create {}", dir.display()))?;
+    Ok(dir)
+}
+
+/// A download that doesn't match its object, so what the provider stores is corrupt.
+#[derive(Debug)]
+pub struct Mismatch(pub String);
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// A download in progress.
+///
+/// Downloads are kept as `<oid>.part` when they fail, so the next attempt can resume them.
+/// When another transfer is downloading the same object already, the download goes to a unique
+/// name instead, which is removed again when dropped.
+pub struct PartialDownload {
+    path: PathBuf,
+    object: Object,
+    /// the locked lock file while this download owns `<oid>.part`
+    lock: Option<std::fs::File>,
+}
+
+impl PartialDownload {
+    /// start or resume a download of the object in the repository's temp dir
+    pub fn new(object: &Object) -> Result<Self> {
+        Self::new_in(&tmp_dir()?, object)
+    }
+
+    pub fn new_in(dir: &Path, object: &Object) -> Result<Self> {
+        let path = dir.join(format!("{}{}", &object.oid, PARTIAL_SUFFIX));
+        let lock = dir.join(format!("{}{}{}", &object.oid, PARTIAL_SUFFIX, LOCK_SUFFIX));
+
+        let lock_file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&lock)
+            .with_context(|| format!("could not create {}", lock.display()))?;
+
+        match lock_file.try_lock_exclusive() {
+            Ok(()) => {
+                std::fs::OpenOptions::new().create(true).append(true).open(&path)?;
+
+                Ok(Self {
+                    path,
+                    object: object.clone(),
+                    lock: Some(lock_file),
+                })
+            }
+            Err(err) if err.kind() == fs2::lock_contended_error().kind() => {
+                log::debug!("{} is being downloaded elsewhere, not resuming", &object.oid);
+
+                let (_, path) = tempfile::Builder::new()
+                    .prefix(&format!("{}-", &object.oid))
+                    .suffix(PARTIAL_SUFFIX)
+                    .tempfile_in(dir)?
+                    .keep()?;
+
+                Ok(Self {
+                    path,
+                    object: object.clone(),
+                    lock: None,
+                })
+            }
+            Err(err) => Err(err).with_context(|| format!("could not lock {}", lock.display())),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// how many bytes an earlier attempt already downloaded
+    pub fn resume_from(&self) -> Result<u64> {
+        let downloaded = std::fs::metadata(&self.path)?.len();
+
+        // more than the object can hold, so it's not a prefix of it
+        if downloaded > self.object.size {
+            self.restart()?;
+            return Ok(0);
+        }
+
+        Ok(downloaded)
+    }
+
+    /// throw away what was downloaded so far
+    pub fn restart(&self) -> Result<()> {
+        std::fs::OpenOptions::new().write(true).truncate(true).open(&self.path)?;
+        Ok(())
+    }
+
+    /// a handle that appends to the download
+    pub fn tokio_file(&self) -> Result<tokio::fs::File> {
+        let file = std::fs::OpenOptions::new().append(true).open(&self.path)?;
+        Ok(tokio::fs::File::from_std(file))
+    }
+
+    /// append everything from `reader`, such as an HTTP response body, to the download
+    pub async fn write_from(&self, reader: impl AsyncRead + Unpin, limiter: Option<Arc<RateLimiter>>) -> Result<()> {
+        throttle::copy_to(reader, self.tokio_file()?, limiter).await?;
+        Ok(())
+    }
+
+    /// Download the response to `request` into the partial download.
+    ///
+    /// With `resume`, the provider's `supports_range`, a download an earlier attempt left behind is
+    /// resumed with a `Range` request, otherwise it is thrown away. Servers that ignore the range
+    /// send the whole object, which replaces what was downloaded before.
+    pub async fn fetch(&self, request: isahc::http::request::Builder, limiter: Option<Arc<RateLimiter>>, resume: bool) -> Result<()> {
+        let offset = if resume {
+            self.resume_from()?
+        } else {
+            self.restart()?;
+            0
+        };
+
+        let request = if offset > 0 {
+            log::info!("resuming download of {} at byte {}", &self.object.oid, offset);
+            request.header("Range", format!("bytes={}-", offset))
+        } else {
+            request
+        };
+
+        let mut response = request.body(())?.send_async().await?;
+        let status = response.status();
+
+        if status == StatusCode::RANGE_NOT_SATISFIABLE && (offset == self.object.size || self.object.size == 0) {
+            // everything was downloaded already, only the verification is left
+            return Ok(());
+        }
+
+        if !status.is_success() {
+            return Err(anyhow::anyhow!("download of {} failed: {}", &self.object.oid, status));
+        }
+
+        if status != StatusCode::PARTIAL_CONTENT && offset > 0 {
+            log::debug!("range not supported for {}, downloading all of it", &self.object.oid);
+            self.restart()?;
+        }
+
+        self.write_from(response.body_mut(), limiter).await
+    }
+
+    /// Check the size and hash of the download and move it into place.
+    ///
+    /// A download that doesn't match is removed, so the next attempt starts over.
+    /// Returns the path of the completed download.
+    pub async fn complete(self) -> Result<PathBuf> {
+        let verified = self.verify().await;
+
+        if verified.is_err() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+        verified?;
+
+        let path = self.path.to_string_lossy();
+        let final_path = PathBuf::from(path.trim_end_matches(PARTIAL_SUFFIX));
+
+        std::fs::rename(&self.path, &final_path)?;
+
+        Ok(final_path)
+    }
+
+    /// Check the download against the object.
+    ///
+    /// A size of 0 is taken as unknown, as for objects only known from a mapping, so only the
+    /// hash is checked. An object that really is empty still has to hash to the empty oid.
+    async fn verify(&self) -> Result<()> {
+        let size = tokio::fs::metadata(&self.path).await?.len();
+
+        if self.object.size != 0 && size != self.object.size {
+            return Err(Mismatch(format!(
+                "downloaded {} bytes for {}, expected {}", size, &self.object.oid, self.object.size
+            )).into());
+        }
+
+        let oid = oid_of_file(&self.path).await?;
+
+        if oid != self.object.oid {
+            return Err(Mismatch(format!(
+                "download for {} hashes to {}", &self.object.oid, oid
+            )).into());
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for PartialDownload {
+    fn drop(&mut self) {
+        // with the lock, keep what we have for the next attempt. The lock file itself stays, another
+        // transfer may have opened it already, and closing it releases the lock
+        if self.lock.is_none() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Remove partial downloads left behind by transfers that crashed or were killed.
+pub fn clean_stale() -> Result<()> {
+    clean_stale_in(&tmp_dir()?, STALE_AFTER)
+}
+
+fn clean_stale_in(dir: &Path, stale_after: Duration) -> Result<()> {
+    let now = SystemTime::now();
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if !name.ends_with(PARTIAL_SUFFIX) && !name.ends_with(LOCK_SUFFIX) {
+            continue;
+        }
+
+        let modified = entry.metadata()?.modified()?;
+
+        if now.duration_since(modified).unwrap_or_default() >= stale_after && !is_locked(&entry.path()) {
+            log::debug!("removing stale partial download {}", entry.path().display());
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+
+    Ok(())
+}
+
+/// whether a running transfer holds the lock of a partial download or lock file
+fn is_locked(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    let lock = if name.ends_with(LOCK_SUFFIX) {
+        PathBuf::from(name.as_ref())
+    } else {
+        PathBuf::from(format!("{}{}", name, LOCK_SUFFIX))
+    };
+
+    match std::fs::File::open(&lock) {
+        Ok(file) => file.try_lock_exclusive().is_err(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oid::oid_of_bytes;
+    use pretty_assertions::assert_eq;
+
+    const FILE: &[u8] = b"hello world";
+
+    fn object() -> Object {
+        Object {
+            oid: oid_of_bytes(FILE),
+            size: FILE.len() as u64,
+        }
+    }
+
+    /// the files in the dir, apart from lock files
+    fn downloads_in(dir: &Path) -> usize {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .filter(|entry| !entry.as_ref().unwrap().file_name().to_string_lossy().ends_with(LOCK_SUFFIX))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn complete_moves_verified_download_into_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let partial = PartialDownload::new_in(dir.path(), &object()).unwrap();
+        std::fs::write(partial.path(), FILE).unwrap();
+
+        let path = partial.complete().await.unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), FILE);
+        assert!(!path.to_string_lossy().ends_with(PARTIAL_SUFFIX));
+        assert_eq!(downloads_in(dir.path()), 1);
+    }
+
+    #[test]
+    fn failed_download_is_resumed_by_the_next_attempt() {
+        let dir = tempfile::tempdir().unwrap();
+
+        {
+            let partial = PartialDownload::new_in(dir.path(), &object()).unwrap();
+            std::fs::write(partial.path(), &FILE[..5]).unwrap();
+        }
+
+        let partial = PartialDownload::new_in(dir.path(), &object()).unwrap();
+        assert_eq!(partial.resume_from().unwrap(), 5);
+
+        // a concurrent download of the same object starts from scratch under its own name
+        let concurrent = PartialDownload::new_in(dir.path(), &object()).unwrap();
+        assert_ne!(concurrent.path(), partial.path());
+        assert_eq!(concurrent.resume_from().unwrap(), 0);
+    }
+
+    #[test]
+    fn download_is_resumed_after_a_crash_left_the_lock_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(format!("{}{}", object().oid, PARTIAL_SUFFIX));
+
+        // what a killed transfer leaves behind
+        std::fs::write(&path, &FILE[..5]).unwrap();
+        std::fs::write(format!("{}{}", path.display(), LOCK_SUFFIX), b"").unwrap();
+
+        let partial = PartialDownload::new_in(dir.path(), &object()).unwrap();
+        assert_eq!(partial.path(), path);
+        assert_eq!(partial.resume_from().unwrap(), 5);
+    }
+
+    #[tokio::test]
+    async fn complete_rejects_and_removes_corrupt_download() {
+        let dir = tempfile::tempdir().unwrap();
+        let partial = PartialDownload::new_in(dir.path(), &object()).unwrap();
+        std::fs::write(partial.path(), b"hello worle").unwrap();
+
+        assert!(partial.complete().await.is_err());
+        assert_eq!(downloads_in(dir.path()), 0);
+    }
+
+    #[tokio::test]
+    async fn download_of_unknown_size_is_verified_by_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let unknown_size = Object { size: 0, ..object() };
+
+        let partial = PartialDownload::new_in(dir.path(), &unknown_size).unwrap();
+        std::fs::write(partial.path(), FILE).unwrap();
+        assert!(partial.complete().await.is_ok());
+
+        let partial = PartialDownload::new_in(dir.path(), &unknown_size).unwrap();
+        std::fs::write(partial.path(), b"hello worle").unwrap();
+        assert!(partial.complete().await.is_err());
+    }
+
+    #[test]
+    fn clean_stale_only_removes_partial_downloads() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("abc-123.part"), FILE).unwrap();
+        std::fs::write(dir.path().join("abc-456"), FILE).unwrap();
+
+        clean_stale_in(dir.path(), Duration::from_secs(0)).unwrap();
+
+        std::fs::write(dir.path().join("abc-789.part.lock"), b"").unwrap();
+        clean_stale_in(dir.path(), Duration::from_secs(0)).unwrap();
+
+        let remaining: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(remaining, vec!["abc-456".to_string()]);
+    }
+}
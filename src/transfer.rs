@@ -1,12 +1,15 @@
 use anyhow::{Context, Result};
 use futures::{Stream, StreamExt};
-use std::{io::Write, path::Path, path::PathBuf};
+use std::{collections::HashMap, io::Write, path::Path, path::PathBuf, time::Instant};
 use tokio::io::{AsyncBufRead, AsyncBufReadExt};
 use git2::Config;
 
-use git_lfs_spec::transfer::custom::{self, Complete, Error, Event, Operation, Progress};
+use git_lfs_spec::transfer::custom::{self, Complete, Error, Event, Operation, Progress, Upload};
+use git_lfs_spec::Object;
 use crate::providers::{SkynetProvider, StorJProvider, UploadStrategy};
 
+use crate::audit::AuditLog;
+use crate::hooks::{HookEvent, Hooks, TransferRecord, TransferResult};
 use crate::provider::StorageProvider;
 
 pub fn read_events(
@@ -23,21 +26,143 @@ pub fn read_events(
 
 const INTERNAL_SERVER_ERROR: i32 = 500;
 
+/// error code of objects larger than the provider accepts
+const PAYLOAD_TOO_LARGE: i32 = 413;
+
+/// git config switch that turns every transfer into a dry run
+pub const DRY_RUN_CONFIG_KEY: &str = "lfs-web3.dryrun";
+
+/// Every object with a local copy, as the uploads a push may ask for.
+///
+/// git-lfs sends uploads one at a time and waits for each to complete, so their existence checks
+/// can only be batched if they are made before git-lfs asks.
+pub fn local_uploads() -> Vec<Upload> {
+    let objects = crate::repo::open().and_then(|repo| {
+        Ok(crate::pointer::local_objects(&repo)?
+            .into_iter()
+            .map(|object| Upload {
+                path: crate::pointer::local_object_path(&repo, &object.oid),
+                object,
+            })
+            .collect())
+    });
+
+    objects.unwrap_or_else(|err| {
+        log::warn!("could not list the local objects, checking uploads one by one: {:#}", err);
+        vec![]
+    })
+}
+
+/// what a dry run push would have done
+#[derive(Debug, Default)]
+struct DryRunTotals {
+    uploads: u64,
+    upload_bytes: u64,
+    present: u64,
+    present_bytes: u64,
+}
+
+impl DryRunTotals {
+    fn record(&mut self, upload: &Upload, uploaded: bool) {
+        let size = upload.object.size;
+
+        if uploaded {
+            self.present += 1;
+            self.present_bytes += size;
+            log::info!("dry run: {} ({} bytes) is already uploaded", &upload.object.oid, size);
+        } else {
+            self.uploads += 1;
+            self.upload_bytes += size;
+            log::info!("dry run: would upload {} ({} bytes)", &upload.object.oid, size);
+        }
+    }
+}
+
+fn operation_name(operation: &Operation) -> &'static str {
+    match operation {
+        Operation::Upload => "upload",
+        Operation::Download => "download",
+    }
+}
+
+/// what hooks are told about a transfer
+fn transfer_record(
+    client: &impl StorageProvider,
+    operation: &Operation,
+    object: &Object,
+    started: Instant,
+    result: std::result::Result<TransferResult, String>,
+) -> TransferRecord {
+    let (result, error) = match result {
+        Ok(result) => (result, None),
+        Err(error) => (TransferResult::Failed, Some(error)),
+    };
+
+    TransferRecord {
+        operation: operation_name(operation),
+        oid: object.oid.clone(),
+        size: object.size,
+        provider: client.name(),
+        address: client.address(&object.oid),
+        duration_ms: started.elapsed().as_millis() as u64,
+        result,
+        error,
+    }
+}
+
+/// tell the audit log and the hooks about a finished transfer
+async fn report(audit: &AuditLog, hooks: &Hooks, remote: &str, record: &TransferRecord) {
+    audit.append(record, remote);
+    hooks.run(&HookEvent::Complete(record.clone())).await;
+}
+
+/// Handle the events git-lfs sends us.
+///
+/// When an upload starts, the existence of the objects `upcoming` returns is checked in one
+/// `is_uploaded_many` batch, and upload events are answered from it.
+/// In a dry run uploads are only checked and reported as complete,
+/// nothing is written to the provider or git config.
+/// Transfers are recorded in the audit log and hooks are called after every transfer and once git-lfs is done,
+/// except in a dry run.
 pub fn transfer(
     client: impl StorageProvider,
     input_event_stream: impl Stream<Item = Result<Event>>,
+    dry_run: bool,
+    hooks: Hooks,
+    audit: AuditLog,
+    upcoming: impl FnOnce() -> Vec<Upload>,
 ) -> impl Stream<Item = Result<Event>> {
     let mut init_opt = None;
+    let mut totals = DryRunTotals::default();
+    let capabilities = client.capabilities();
+    let started_batch = Instant::now();
+    let mut records = vec![];
+    let mut upcoming = Some(upcoming);
+    let mut prefetched: HashMap<String, bool> = HashMap::new();
 
     async_stream::stream! {
         futures_util::pin_mut!(input_event_stream);
 
         // wait for json object messages to be sent to the process
-        while let Some(event) = input_event_stream.next().await.transpose()? {
+        while let Some(event) = input_event_stream.next().await {
+            let event = event?;
             log::debug!("received event from stdin stream: {:#?}", &event);
 
             match (init_opt.as_ref(), event) {
                 (None, Event::Init(init)) => {
+                    if matches!(init.operation, Operation::Upload) {
+                        let uploads = upcoming.take().map(|upcoming| upcoming()).unwrap_or_default();
+
+                        if !uploads.is_empty() {
+                            log::debug!("checking {} local objects at once", uploads.len());
+
+                            match client.is_uploaded_many(&uploads).await {
+                                Ok(uploaded) => prefetched = uploaded,
+                                Err(err) => log::warn!("batch existence check failed, checking one by one: {:#}", err),
+                            }
+                        }
+                    }
+
                     init_opt = Some(init);
                     yield Ok(Event::AcknowledgeInit)
                 }
@@ -48,15 +173,46 @@ pub fn transfer(
                     yield Err(anyhow::anyhow!("Unexpected init event: {:?}", init))
                 }
 
-                (Some(_), Event::Terminate) => {
-                    break
+                (Some(init), Event::Terminate) => {
+                    if !dry_run && !records.is_empty() {
+                        hooks.run(&HookEvent::Batch {
+                            operation: operation_name(&init.operation),
+                            provider: client.name(),
+                            objects: std::mem::take(&mut records),
+                            duration_ms: started_batch.elapsed().as_millis() as u64,
+                        }).await;
+                    }
+
+                    if dry_run {
+                        let summary = format!(
+                            "dry run: would upload {} objects ({} bytes), {} objects ({} bytes) already uploaded",
+                            totals.uploads, totals.upload_bytes, totals.present, totals.present_bytes
+                        );
+                        log::info!("{}", &summary);
+                        eprintln!("{}", summary);
+                    } else if let Err(err) = client.finish().await {
+                        log::warn!("could not finish the transfers: {:#}", err);
+                        eprintln!("warning: could not finish the transfers: {:#}", err);
+                    }
+                    return
                 }
                 (Some(init), event) => {
                     match (event, &init.operation) {
                         (Event::Download(download), Operation::Download) => {
-                            yield client
-                                .download(&download)
-                                .await
+                            let started = Instant::now();
+                            let result = client.download(&download).await;
+
+                            let record = transfer_record(
+                                &client,
+                                &init.operation,
+                                &download.object,
+                                started,
+                                result.as_ref().map(|_| TransferResult::Transferred).map_err(|err| format!("{:#}", err)),
+                            );
+                            report(&audit, &hooks, &init.remote, &record).await;
+                            records.push(record);
+
+                            yield result
                                 .map(|path| Event::Complete(
                                     Complete {
                                         oid: download.object.oid.clone(),
@@ -66,17 +222,72 @@ pub fn transfer(
                                 ))
                         }
 
+                        (Event::Upload(upload), Operation::Upload)
+                            if capabilities.max_object_size.map_or(false, |max| upload.object.size > max) => {
+                            let message = format!(
+                                "{} bytes is more than {} accepts ({} bytes)",
+                                upload.object.size,
+                                client.name(),
+                                capabilities.max_object_size.unwrap_or_default()
+                            );
+
+                            if !dry_run {
+                                let record = transfer_record(&client, &init.operation, &upload.object, Instant::now(), Err(message.clone()));
+                                report(&audit, &hooks, &init.remote, &record).await;
+                                records.push(record);
+                            }
+
+                            // fails just this object, git-lfs reports it and carries on with the others
+                            yield Ok(Event::Complete(
+                                Complete {
+                                    oid: upload.object.oid.clone(),
+                                    result: Some(custom::Result::Error(Error {
+                                        code: PAYLOAD_TOO_LARGE,
+                                        message,
+                                    })),
+                                }
+                                .into(),
+                            ))
+                        }
+
                         (Event::Upload(upload), Operation::Upload) => {
-                            yield client
-                                .upload_if_needed(&upload)
-                                .await
-                                .map(|_| Event::Complete(
-                                    Complete {
-                                        oid: upload.object.oid.clone(),
-                                        result: None,
-                                    }
-                                    .into(),
-                                ))
+                            let started = Instant::now();
+                            let uploaded = match prefetched.remove(&upload.object.oid) {
+                                Some(uploaded) => uploaded,
+                                None => client.is_uploaded(&upload).await.unwrap_or_else(|err| {
+                                    log::warn!("existence check for {} failed: {:#}", &upload.object.oid, err);
+                                    false
+                                }),
+                            };
+
+                            let result = if dry_run {
+                                totals.record(&upload, uploaded);
+                                Ok(())
+                            } else if uploaded {
+                                Ok(())
+                            } else {
+                                client.upload(&upload).await
+                            };
+
+                            if !dry_run {
+                                let outcome = match &result {
+                                    Ok(()) if uploaded => Ok(TransferResult::AlreadyPresent),
+                                    Ok(()) => Ok(TransferResult::Transferred),
+                                    Err(err) => Err(format!("{:#}", err)),
+                                };
+
+                                let record = transfer_record(&client, &init.operation, &upload.object, started, outcome);
+                                report(&audit, &hooks, &init.remote, &record).await;
+                                records.push(record);
+                            }
+
+                            yield result.map(|_| Event::Complete(
+                                Complete {
+                                    oid: upload.object.oid.clone(),
+                                    result: None,
+                                }
+                                .into(),
+                            ))
                         },
 
                         (event, _) => {
@@ -95,7 +306,7 @@ mod tests {
 
     use super::*;
     use git_lfs_spec::{
-        transfer::custom::{Download, Event, Init, Result, Upload},
+        transfer::custom::{Event, Init, Result, Upload},
         Object,
     };
     use pretty_assertions::assert_eq;
@@ -123,6 +334,176 @@ mod tests {
         assert_eq!(events, &[init]);
     }
 
+    /// a provider that holds nothing and refuses to store anything
+    struct EmptyProvider;
+
+    #[async_trait::async_trait]
+    impl StorageProvider for EmptyProvider {
+        fn name(&self) -> &'static str {
+            "empty"
+        }
+
+        async fn download_writer(
+            &self,
+            _object: &Object,
+            _writer: &mut (dyn tokio::io::AsyncWrite + Send + Unpin),
+        ) -> anyhow::Result<()> {
+            Err(anyhow::anyhow!("nothing to download"))
+        }
+
+        async fn upload_reader(&self, _object: &Object, _reader: crate::provider::ObjectReader) -> anyhow::Result<()> {
+            panic!("a dry run must not upload")
+        }
+
+        async fn is_uploaded(&self, _upload: &Upload) -> anyhow::Result<bool> {
+            Ok(false)
+        }
+
+        async fn list(&self) -> anyhow::Result<Vec<crate::provider::RemoteObject>> {
+            Ok(vec![])
+        }
+
+        async fn delete(&self, _oid: &str) -> anyhow::Result<()> {
+            panic!("a dry run must not delete")
+        }
+    }
+
+    #[tokio::test]
+    async fn transfer_dry_run_completes_uploads_without_uploading() {
+        let input_events = [
+            Event::Init(Init {
+                operation: Operation::Upload,
+                remote: "origin".to_string(),
+                concurrent: true,
+                concurrenttransfers: Some(3),
+            }),
+            Event::Upload(
+                Upload {
+                    object: Object {
+                        oid: OID.to_string(),
+                        size: SIZE,
+                    },
+                    path: PathBuf::from("does-not-matter"),
+                }
+                .into(),
+            ),
+            Event::Terminate,
+        ];
+        let output_stream = transfer(
+            EmptyProvider,
+            futures::stream::iter(input_events.iter().cloned().map(anyhow::Result::Ok)),
+            true,
+            Hooks::default(),
+            AuditLog::default(),
+            Vec::new,
+        );
+        futures_util::pin_mut!(output_stream);
+        let mut events = vec![];
+        while let Some(output) = output_stream.next().await {
+            events.push(output.unwrap());
+        }
+        assert_eq!(events, &[
+            Event::AcknowledgeInit,
+            Event::Complete(
+                Complete {
+                    oid: OID.to_string(),
+                    result: None,
+                }
+                .into(),
+            ),
+        ]);
+    }
+
+    /// a provider that holds every object and counts how it is asked about them
+    #[derive(Clone, Default)]
+    struct CountingProvider {
+        single_checks: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        batch_checks: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl StorageProvider for CountingProvider {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        async fn download_writer(
+            &self,
+            _object: &Object,
+            _writer: &mut (dyn tokio::io::AsyncWrite + Send + Unpin),
+        ) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn upload_reader(&self, _object: &Object, _reader: crate::provider::ObjectReader) -> anyhow::Result<()> {
+            panic!("objects that are present must not be uploaded")
+        }
+
+        async fn is_uploaded(&self, _upload: &Upload) -> anyhow::Result<bool> {
+            self.single_checks.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(true)
+        }
+
+        async fn is_uploaded_many(&self, uploads: &[Upload]) -> anyhow::Result<HashMap<String, bool>> {
+            self.batch_checks.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(uploads.iter().map(|upload| (upload.object.oid.clone(), true)).collect())
+        }
+
+        async fn list(&self) -> anyhow::Result<Vec<crate::provider::RemoteObject>> {
+            Ok(vec![])
+        }
+
+        async fn delete(&self, _oid: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn transfer_checks_upcoming_uploads_in_one_batch() {
+        let upload = |oid: &str| Upload {
+            object: Object {
+                oid: oid.to_string(),
+                size: SIZE,
+            },
+            path: PathBuf::from("does-not-matter"),
+        };
+        let other = "a".repeat(64);
+
+        let input_events = vec![
+            Event::Init(Init {
+                operation: Operation::Upload,
+                remote: "origin".to_string(),
+                concurrent: true,
+                concurrenttransfers: Some(3),
+            }),
+            Event::Upload(upload(OID).into()),
+            Event::Upload(upload(&other).into()),
+            Event::Terminate,
+        ];
+        let upcoming = vec![upload(OID), upload(&other)];
+
+        let client = CountingProvider::default();
+        let output_stream = transfer(
+            client.clone(),
+            futures::stream::iter(input_events.into_iter().map(anyhow::Result::Ok)),
+            false,
+            Hooks::default(),
+            AuditLog::default(),
+            move || upcoming,
+        );
+        futures_util::pin_mut!(output_stream);
+        let mut completed = 0;
+        while let Some(output) = output_stream.next().await {
+            if let Event::Complete(_) = output.unwrap() {
+                completed += 1;
+            }
+        }
+
+        assert_eq!(completed, 2);
+        assert_eq!(client.batch_checks.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(client.single_checks.load(std::sync::atomic::Ordering::SeqCst), 0);
+    }
+
     // #[tokio::test]
     // #[ignore]
     // async fn transfer_handles_events_as_expected_for_download() {
@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use git_lfs_spec::transfer::custom::{Download, Upload};
+use git_lfs_spec::Object;
+use rusqlite::{params, Connection, OptionalExtension};
+use tokio::io::AsyncWrite;
+
+use crate::locks::LockStore;
+use crate::provider::{Capabilities, ObjectReader, RemoteObject, StorageProvider};
+use crate::repo;
+
+/// git config key of how many seconds an upload recorded in the index is trusted, 0 turns the index off
+pub const TTL_CONFIG_KEY: &str = "lfs-web3.uploadindex.ttl";
+
+/// git config switch that makes every existence check ask the provider, like `--revalidate`
+pub const REVALIDATE_CONFIG_KEY: &str = "lfs-web3.uploadindex.revalidate";
+
+const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A local SQLite database of the objects each provider target is known to hold.
+///
+/// Objects are recorded when they are uploaded or found on the provider, so the next push
+/// doesn't have to ask the provider about them again until the TTL is over. Records are keyed by
+/// `StorageProvider::target`, kept in the `provider` column.
+pub struct UploadIndex {
+    connection: Mutex<Connection>,
+    ttl: Duration,
+}
+
+impl UploadIndex {
+    pub fn open(path: &Path, ttl: Duration) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let connection = Connection::open(path)
+            .with_context(|| format!("could not open the upload index {}", path.display()))?;
+
+        // concurrent git-lfs processes share the database
+        connection.busy_timeout(Duration::from_secs(5))?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS uploads (
+                provider TEXT NOT NULL,
+                oid TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                uploaded_at INTEGER NOT NULL,
+                PRIMARY KEY (provider, oid)
+            )",
+        )?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+            ttl,
+        })
+    }
+
+    /// The index of the repository, at `<lfs dir>/web3-uploads.sqlite`.
+    ///
+    /// `None` when it is turned off with a TTL of 0.
+    pub fn from_config() -> Result<Option<Self>> {
+        let repo = repo::open()?;
+        let config = repo::config()?;
+
+        let ttl = match config.get_i64(TTL_CONFIG_KEY) {
+            Ok(seconds) if seconds <= 0 => return Ok(None),
+            Ok(seconds) => Duration::from_secs(seconds as u64),
+            Err(_) => DEFAULT_TTL,
+        };
+
+        Ok(Some(Self::open(&repo::lfs_dir(&repo).join("web3-uploads.sqlite"), ttl)?))
+    }
+
+    /// whether the target was known to hold the object, with the same size, within the TTL
+    pub fn is_known(&self, target: &str, object: &Object) -> Result<bool> {
+        let oldest = Utc::now().timestamp() - self.ttl.as_secs() as i64;
+
+        let size: Option<i64> = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT size FROM uploads WHERE provider = ?1 AND oid = ?2 AND uploaded_at >= ?3",
+                params![target, &object.oid, oldest],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(size == Some(object.size as i64))
+    }
+
+    pub fn record(&self, target: &str, object: &Object) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO uploads (provider, oid, size, uploaded_at) VALUES (?1, ?2, ?3, ?4)",
+            params![target, &object.oid, object.size as i64, Utc::now().timestamp()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn forget(&self, target: &str, oid: &str) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "DELETE FROM uploads WHERE provider = ?1 AND oid = ?2",
+            params![target, oid],
+        )?;
+
+        Ok(())
+    }
+}
+
+/// A provider whose existence checks are answered from the upload index where possible.
+///
+/// With `revalidate` every check goes to the provider, and the index is refreshed with the answers.
+pub struct Indexed<P> {
+    inner: P,
+    /// the inner provider's target, the index key
+    target: String,
+    index: Option<UploadIndex>,
+    revalidate: bool,
+}
+
+impl<P: StorageProvider> Indexed<P> {
+    pub fn new(inner: P, index: Option<UploadIndex>, revalidate: bool) -> Self {
+        Self {
+            target: inner.target(),
+            inner,
+            index,
+            revalidate,
+        }
+    }
+
+    /// wrap a provider in the repository's upload index, if it is turned on
+    pub fn from_config(inner: P, revalidate: bool) -> Self {
+        let index = UploadIndex::from_config().unwrap_or_else(|err| {
+            log::warn!("not using the upload index: {:#}", err);
+            None
+        });
+        let revalidate = revalidate || repo::config()
+            .and_then(|config| Ok(config.get_bool(REVALIDATE_CONFIG_KEY)?))
+            .unwrap_or(false);
+
+        Self::new(inner, index, revalidate)
+    }
+
+    fn is_known(&self, object: &Object) -> bool {
+        if self.revalidate {
+            return false;
+        }
+
+        let known = self.index.as_ref().map_or(false, |index| {
+            index.is_known(&self.target, object).unwrap_or_else(|err| {
+                log::warn!("upload index lookup for {} failed: {:#}", &object.oid, err);
+                false
+            })
+        });
+
+        if known {
+            log::debug!("{} is in the upload index, not asking {}", &object.oid, self.inner.name());
+        }
+
+        known
+    }
+
+    /// remember whether the provider holds the object, errors are only logged
+    fn update(&self, object: &Object, uploaded: bool) {
+        if let Some(index) = &self.index {
+            let updated = if uploaded {
+                index.record(&self.target, object)
+            } else {
+                index.forget(&self.target, &object.oid)
+            };
+
+            if let Err(err) = updated {
+                log::warn!("could not update the upload index for {}: {:#}", &object.oid, err);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<P: StorageProvider> StorageProvider for Indexed<P> {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn target(&self) -> String {
+        self.target.clone()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    async fn upload_reader(&self, object: &Object, reader: ObjectReader) -> Result<()> {
+        self.inner.upload_reader(object, reader).await?;
+        self.update(object, true);
+        Ok(())
+    }
+
+    async fn download_writer(&self, object: &Object, writer: &mut (dyn AsyncWrite + Send + Unpin)) -> Result<()> {
+        self.inner.download_writer(object, writer).await
+    }
+
+    async fn download(&self, obj: &Download) -> Result<String> {
+        self.inner.download(obj).await
+    }
+
+    async fn upload(&self, obj: &Upload) -> Result<()> {
+        self.inner.upload(obj).await?;
+        self.update(&obj.object, true);
+        Ok(())
+    }
+
+    async fn is_uploaded(&self, obj: &Upload) -> Result<bool> {
+        if self.is_known(&obj.object) {
+            return Ok(true);
+        }
+
+        let uploaded = self.inner.is_uploaded(obj).await?;
+        self.update(&obj.object, uploaded);
+
+        Ok(uploaded)
+    }
+
+    async fn is_uploaded_many(&self, objs: &[Upload]) -> Result<HashMap<String, bool>> {
+        let (known, unknown): (Vec<Upload>, Vec<Upload>) = objs.iter().cloned().partition(|obj| self.is_known(&obj.object));
+
+        let mut uploaded = if unknown.is_empty() {
+            HashMap::new()
+        } else {
+            self.inner.is_uploaded_many(&unknown).await?
+        };
+
+        for obj in &unknown {
+            self.update(&obj.object, uploaded.get(&obj.object.oid).copied().unwrap_or(false));
+        }
+        for obj in known {
+            uploaded.insert(obj.object.oid, true);
+        }
+
+        Ok(uploaded)
+    }
+
+    async fn list(&self) -> Result<Vec<RemoteObject>> {
+        self.inner.list().await
+    }
+
+    async fn delete(&self, oid: &str) -> Result<()> {
+        self.inner.delete(oid).await?;
+
+        if let Some(index) = &self.index {
+            if let Err(err) = index.forget(&self.target, oid) {
+                log::warn!("could not update the upload index for {}: {:#}", oid, err);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn address(&self, oid: &str) -> Option<String> {
+        self.inner.address(oid)
+    }
+
+    fn lock_store(&self) -> Option<&dyn LockStore> {
+        self.inner.lock_store()
+    }
+
+    async fn finish(&self) -> Result<()> {
+        self.inner.finish().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use pretty_assertions::assert_eq;
+
+    const OID: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+    fn object() -> Object {
+        Object {
+            oid: OID.to_string(),
+            size: 11,
+        }
+    }
+
+    fn upload() -> Upload {
+        Upload {
+            object: object(),
+            path: Default::default(),
+        }
+    }
+
+    /// a provider that holds every object and counts how often it is asked
+    #[derive(Default)]
+    struct CountingProvider {
+        target: String,
+        checks: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl StorageProvider for CountingProvider {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        fn target(&self) -> String {
+            self.target.clone()
+        }
+
+        async fn upload_reader(&self, _object: &Object, _reader: ObjectReader) -> Result<()> {
+            Ok(())
+        }
+
+        async fn download_writer(&self, _object: &Object, _writer: &mut (dyn AsyncWrite + Send + Unpin)) -> Result<()> {
+            Ok(())
+        }
+
+        async fn is_uploaded(&self, _upload: &Upload) -> Result<bool> {
+            self.checks.fetch_add(1, Ordering::SeqCst);
+            Ok(true)
+        }
+
+        async fn list(&self) -> Result<Vec<RemoteObject>> {
+            Ok(vec![])
+        }
+
+        async fn delete(&self, _oid: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn index_only_trusts_matching_records_within_the_ttl() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = UploadIndex::open(&dir.path().join("uploads.sqlite"), DEFAULT_TTL).unwrap();
+
+        assert!(!index.is_known("storj", &object()).unwrap());
+
+        index.record("storj", &object()).unwrap();
+        assert!(index.is_known("storj", &object()).unwrap());
+        assert!(!index.is_known("skynet", &object()).unwrap());
+        assert!(!index.is_known("storj", &Object { size: 12, ..object() }).unwrap());
+
+        index.forget("storj", OID).unwrap();
+        assert!(!index.is_known("storj", &object()).unwrap());
+    }
+
+    #[test]
+    fn expired_records_are_not_trusted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("uploads.sqlite");
+
+        UploadIndex::open(&path, DEFAULT_TTL).unwrap().record("storj", &object()).unwrap();
+        std::thread::sleep(Duration::from_millis(1100));
+
+        let index = UploadIndex::open(&path, Duration::from_secs(0)).unwrap();
+        assert!(!index.is_known("storj", &object()).unwrap());
+    }
+
+    #[tokio::test]
+    async fn known_objects_are_not_checked_again_unless_revalidating() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("uploads.sqlite");
+
+        let indexed = Indexed::new(CountingProvider::default(), Some(UploadIndex::open(&path, DEFAULT_TTL).unwrap()), false);
+        assert!(indexed.is_uploaded(&upload()).await.unwrap());
+        assert!(indexed.is_uploaded(&upload()).await.unwrap());
+        assert_eq!(indexed.inner.checks.load(Ordering::SeqCst), 1);
+
+        let revalidating = Indexed::new(CountingProvider::default(), Some(UploadIndex::open(&path, DEFAULT_TTL).unwrap()), true);
+        assert!(revalidating.is_uploaded(&upload()).await.unwrap());
+        assert_eq!(revalidating.inner.checks.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn records_of_another_target_are_not_trusted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("uploads.sqlite");
+        let provider = |target: &str| CountingProvider {
+            target: target.to_string(),
+            ..Default::default()
+        };
+
+        let old_bucket = Indexed::new(provider("storj:old"), Some(UploadIndex::open(&path, DEFAULT_TTL).unwrap()), false);
+        assert!(old_bucket.is_uploaded(&upload()).await.unwrap());
+
+        let new_bucket = Indexed::new(provider("storj:new"), Some(UploadIndex::open(&path, DEFAULT_TTL).unwrap()), false);
+        assert!(new_bucket.is_uploaded(&upload()).await.unwrap());
+        assert_eq!(new_bucket.inner.checks.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,246 @@
+use anyhow::Result;
+use futures::StreamExt;
+use git_lfs_spec::transfer::custom::{Download, Upload};
+use serde::Serialize;
+
+use crate::oid::oid_of_file;
+use crate::pointer::{self, Pointer};
+use crate::provider::StorageProvider;
+use crate::repo;
+use crate::tmp::Mismatch;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    /// the provider doesn't have the object
+    Missing,
+    /// the provider doesn't know where the object is stored
+    Unmapped,
+    /// the stored object doesn't hash to its oid
+    Corrupt,
+    /// the object could not be checked
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ObjectReport {
+    pub oid: String,
+    pub size: u64,
+    pub path: String,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub provider: &'static str,
+    pub checked: usize,
+    pub objects: Vec<ObjectReport>,
+}
+
+impl Status {
+    fn label(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Missing => "missing",
+            Status::Unmapped => "unmapped",
+            Status::Corrupt => "corrupt",
+            Status::Error => "error",
+        }
+    }
+}
+
+impl Report {
+    pub fn count(&self, status: Status) -> usize {
+        self.objects.iter().filter(|object| object.status == status).count()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.count(Status::Ok) == self.checked
+    }
+}
+
+/// Check that every LFS object reachable from `revs` (or all refs when empty) can be retrieved.
+///
+/// With `deep`, every object is downloaded and its hash is checked as well.
+pub async fn verify(client: &dyn StorageProvider, revs: &[String], deep: bool) -> Result<Report> {
+    let repo = repo::open()?;
+    let pointers = pointer::pointers_in_history(&repo, revs)?;
+
+    log::debug!("verifying {} LFS objects", pointers.len());
+
+    let objects: Vec<ObjectReport> = futures::stream::iter(pointers.into_values())
+        .map(|pointer| {
+            let upload_path = pointer::local_object_path(&repo, &pointer.object.oid);
+            check(client, pointer, upload_path, deep)
+        })
+        .buffer_unordered(client.capabilities().recommended_concurrency.max(1))
+        .collect()
+        .await;
+
+    Ok(Report {
+        provider: client.name(),
+        checked: objects.len(),
+        objects,
+    })
+}
+
+async fn check(client: &dyn StorageProvider, pointer: Pointer, path: std::path::PathBuf, deep: bool) -> ObjectReport {
+    let Pointer { object, path: pointer_path } = pointer;
+
+    let report = |status, detail: Option<String>| ObjectReport {
+        oid: object.oid.clone(),
+        size: object.size,
+        path: pointer_path.clone(),
+        status,
+        detail,
+    };
+
+    if client.capabilities().needs_mapping && client.address(&object.oid).is_none() {
+        return report(Status::Unmapped, None);
+    }
+
+    let upload = Upload {
+        object: object.clone(),
+        path,
+    };
+
+    match client.is_uploaded(&upload).await {
+        Ok(true) => {}
+        Ok(false) => return report(Status::Missing, None),
+        Err(err) => return report(Status::Error, Some(format!("{:#}", err))),
+    }
+
+    if !deep {
+        return report(Status::Ok, None);
+    }
+
+    let downloaded = match client.download(&Download { object: object.clone() }).await {
+        Ok(downloaded) => downloaded,
+        // downloads are verified on the way, so corrupt objects fail to download
+        Err(err) => match err.chain().find_map(|cause| cause.downcast_ref::<Mismatch>()) {
+            Some(mismatch) => return report(Status::Corrupt, Some(mismatch.to_string())),
+            None => return report(Status::Missing, Some(format!("download failed: {:#}", err))),
+        },
+    };
+
+    let result = match (oid_of_file(&downloaded).await, tokio::fs::metadata(&downloaded).await) {
+        (Ok(oid), Ok(metadata)) if oid == object.oid && metadata.len() == object.size => {
+            report(Status::Ok, None)
+        }
+        (Ok(oid), Ok(metadata)) => report(
+            Status::Corrupt,
+            Some(format!("downloaded {} bytes hashing to {}", metadata.len(), oid))
+        ),
+        (Err(err), _) => report(Status::Error, Some(format!("{:#}", err))),
+        (_, Err(err)) => report(Status::Error, Some(err.to_string())),
+    };
+
+    let _ = tokio::fs::remove_file(&downloaded).await;
+
+    result
+}
+
+/// print the report for humans, listing only the objects that need attention
+pub fn print(report: &Report) {
+    for object in report.objects.iter().filter(|object| object.status != Status::Ok) {
+        match &object.detail {
+            Some(detail) => println!("{:<10} {} {} ({})", object.status.label(), object.oid, object.path, detail),
+            None => println!("{:<10} {} {}", object.status.label(), object.oid, object.path),
+        }
+    }
+
+    println!(
+        "checked {} objects on {}: {} ok, {} missing, {} unmapped, {} corrupt, {} errors",
+        report.checked,
+        report.provider,
+        report.count(Status::Ok),
+        report.count(Status::Missing),
+        report.count(Status::Unmapped),
+        report.count(Status::Corrupt),
+        report.count(Status::Error),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use async_trait::async_trait;
+    use git_lfs_spec::Object;
+    use pretty_assertions::assert_eq;
+    use tokio::io::AsyncWrite;
+
+    use crate::oid::oid_of_bytes;
+    use crate::provider::{ObjectReader, RemoteObject};
+    use crate::tmp::PartialDownload;
+
+    /// a provider that holds every object, but returns `contents` for all of them
+    struct FixedProvider {
+        dir: tempfile::TempDir,
+        contents: &'static [u8],
+    }
+
+    #[async_trait]
+    impl StorageProvider for FixedProvider {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        async fn upload_reader(&self, _object: &Object, _reader: ObjectReader) -> Result<()> {
+            Ok(())
+        }
+
+        async fn download_writer(&self, _object: &Object, _writer: &mut (dyn AsyncWrite + Send + Unpin)) -> Result<()> {
+            Ok(())
+        }
+
+        async fn download(&self, download: &Download) -> Result<String> {
+            let partial = PartialDownload::new_in(self.dir.path(), &download.object)?;
+            std::fs::write(partial.path(), self.contents)?;
+
+            Ok(partial.complete().await?.to_string_lossy().to_string())
+        }
+
+        async fn is_uploaded(&self, _upload: &Upload) -> Result<bool> {
+            Ok(true)
+        }
+
+        async fn list(&self) -> Result<Vec<RemoteObject>> {
+            Ok(vec![])
+        }
+
+        async fn delete(&self, _oid: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn pointer() -> Pointer {
+        Pointer {
+            object: Object {
+                oid: oid_of_bytes(b"hello world"),
+                size: 11,
+            },
+            path: "assets/file.bin".to_string(),
+        }
+    }
+
+    async fn deep_check(contents: &'static [u8]) -> Status {
+        let provider = FixedProvider {
+            dir: tempfile::tempdir().unwrap(),
+            contents,
+        };
+
+        check(&provider, pointer(), PathBuf::new(), true).await.status
+    }
+
+    #[tokio::test]
+    async fn wrong_bytes_are_reported_as_corrupt() {
+        assert_eq!(deep_check(b"hello world").await, Status::Ok);
+        assert_eq!(deep_check(b"hello worle").await, Status::Corrupt);
+        assert_eq!(deep_check(b"hello").await, Status::Corrupt);
+    }
+}